@@ -1,3 +1,6 @@
 fn main() {
-    bare_test_macros::build_test_setup!();
+    // The bare-test link script only applies to the bare metal test image.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        bare_test_macros::build_test_setup!();
+    }
 }
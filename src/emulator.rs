@@ -0,0 +1,736 @@
+//! Software SCMI platform for running agents on the host.
+//!
+//! [`Emulator`] keeps the state of the clocks, performance domains, sensors,
+//! power domains and powercap domains it is built with, and answers the
+//! base, power domain, performance, clock, sensor and powercap protocol
+//! commands sent over a
+//! [`Loopback`](crate::Loopback) transport:
+//!
+//! ```ignore
+//! let transport = Loopback::new(Emulator {
+//!     clocks: vec![EmulatedClock::new("cpu", 1_200_000_000)],
+//!     ..Default::default()
+//! });
+//! let scmi = Scmi::new(transport.clone()).unwrap();
+//! let mut clk = scmi.protocol_clk()?;
+//! clk.rate_set(0, 1_800_000_000)?;
+//! assert_eq!(transport.platform().clocks[0].rate, 1_800_000_000);
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{
+    Platform, ScmiError,
+    codec::{self, Encode, Writer},
+};
+
+const BASE: u8 = 0x10;
+const POWER: u8 = 0x11;
+const PERF: u8 = 0x13;
+const CLOCK: u8 = 0x14;
+const SENSOR: u8 = 0x15;
+const POWERCAP: u8 = 0x18;
+
+const PROTOCOL_VERSION: u8 = 0x0;
+const PROTOCOL_ATTRIBUTES: u8 = 0x1;
+const PROTOCOL_MESSAGE_ATTRIBUTES: u8 = 0x2;
+
+const BASE_DISCOVER_VENDOR: u8 = 0x3;
+const BASE_DISCOVER_SUB_VENDOR: u8 = 0x4;
+const BASE_DISCOVER_IMPLEMENTATION_VERSION: u8 = 0x5;
+const BASE_DISCOVER_LIST_PROTOCOLS: u8 = 0x6;
+
+const POWER_DOMAIN_ATTRIBUTES: u8 = 0x3;
+const POWER_STATE_SET: u8 = 0x4;
+const POWER_STATE_GET: u8 = 0x5;
+
+const PERF_DOMAIN_ATTRIBUTES: u8 = 0x3;
+const PERF_LIMITS_SET: u8 = 0x5;
+const PERF_LIMITS_GET: u8 = 0x6;
+const PERF_LEVEL_SET: u8 = 0x7;
+const PERF_LEVEL_GET: u8 = 0x8;
+
+const CLOCK_ATTRIBUTES: u8 = 0x3;
+const CLOCK_RATE_SET: u8 = 0x5;
+const CLOCK_RATE_GET: u8 = 0x6;
+const CLOCK_CONFIG_SET: u8 = 0x7;
+
+const SENSOR_DESCRIPTION_GET: u8 = 0x3;
+const SENSOR_READING_GET: u8 = 0x6;
+
+const POWERCAP_DOMAIN_ATTRIBUTES: u8 = 0x3;
+const POWERCAP_CAP_GET: u8 = 0x4;
+const POWERCAP_CAP_SET: u8 = 0x5;
+const POWERCAP_PAI_GET: u8 = 0x6;
+const POWERCAP_PAI_SET: u8 = 0x7;
+
+/// Sensor descriptors returned by one `SENSOR_DESCRIPTION_GET`, so that the
+/// answer fits in a 128 byte message.
+const SENSOR_DESCS_PER_MSG: usize = 4;
+
+const CLOCK_ENABLE: u32 = 1 << 0;
+const PERF_SET_LIMITS: u32 = 1 << 31;
+const PERF_SET_LEVEL: u32 = 1 << 30;
+const POWER_SYNC_STATE_SET: u32 = 1 << 30;
+const POWERCAP_CAP_CONFIG: u32 = 1 << 28;
+const POWERCAP_PAI_CONFIG: u32 = 1 << 26;
+const ASYNC_FLAG: u32 = 1 << 0;
+
+crate::scmi_message! {
+    struct PowerStateSet {
+        flags: u32,
+        domain_id: u32,
+        state: u32,
+    }
+
+    struct PerfLimits {
+        max_level: u32,
+        min_level: u32,
+    }
+
+    struct PerfLimitsSet {
+        domain_id: u32,
+        max_level: u32,
+        min_level: u32,
+    }
+
+    struct PerfLevelSet {
+        domain_id: u32,
+        level: u32,
+    }
+
+    struct ClockRateSet {
+        flags: u32,
+        clock_id: u32,
+        rate: u64,
+    }
+
+    struct ClockConfigSet {
+        clock_id: u32,
+        attributes: u32,
+    }
+
+    struct SensorReadingGet {
+        sensor_id: u32,
+        flags: u32,
+    }
+
+    struct PowercapDomainAttributes {
+        attributes: u32,
+        name: [u8; 16],
+        min_pai: u32,
+        max_pai: u32,
+        pai_step: u32,
+        min_power_cap: u32,
+        max_power_cap: u32,
+        power_cap_step: u32,
+        sustainable_power: u32,
+        accuracy: u32,
+        parent_id: u32,
+    }
+
+    struct PowercapValueSet {
+        domain_id: u32,
+        flags: u32,
+        value: u32,
+    }
+}
+
+/// An emulated clock.
+#[derive(Debug, Clone)]
+pub struct EmulatedClock {
+    pub name: &'static str,
+    /// Rate in Hz.
+    pub rate: u64,
+    pub enabled: bool,
+}
+
+impl EmulatedClock {
+    /// A disabled clock running at `rate` once enabled.
+    pub fn new(name: &'static str, rate: u64) -> Self {
+        EmulatedClock {
+            name,
+            rate,
+            enabled: false,
+        }
+    }
+}
+
+/// An emulated performance domain.
+#[derive(Debug, Clone)]
+pub struct EmulatedPerfDomain {
+    pub name: &'static str,
+    pub level: u32,
+    pub min_level: u32,
+    pub max_level: u32,
+}
+
+impl EmulatedPerfDomain {
+    /// A domain allowing `min_level..=max_level`, running at `max_level`.
+    pub fn new(name: &'static str, min_level: u32, max_level: u32) -> Self {
+        EmulatedPerfDomain {
+            name,
+            level: max_level,
+            min_level,
+            max_level,
+        }
+    }
+}
+
+/// An emulated sensor.
+#[derive(Debug, Clone)]
+pub struct EmulatedSensor {
+    pub name: &'static str,
+    /// Sensor type code, e.g. 2 for degrees Celsius.
+    pub sensor_type: u8,
+    /// Reading returned by `SENSOR_READING_GET`.
+    pub value: i64,
+}
+
+/// An emulated power domain.
+#[derive(Debug, Clone)]
+pub struct EmulatedPowerDomain {
+    pub name: &'static str,
+    /// Raw power state, `0` is on and `0x4000_0000` off.
+    pub state: u32,
+}
+
+/// An emulated powercap domain.
+#[derive(Debug, Clone)]
+pub struct EmulatedPowercapDomain {
+    pub name: &'static str,
+    /// Power cap, in milliwatts.
+    pub cap: u32,
+    pub min_cap: u32,
+    pub max_cap: u32,
+    /// Power averaging interval, in microseconds.
+    pub pai: u32,
+    pub min_pai: u32,
+    pub max_pai: u32,
+}
+
+impl EmulatedPowercapDomain {
+    /// A domain allowing caps in `min_cap..=max_cap` and intervals in
+    /// `min_pai..=max_pai`, capped at `max_cap` every `max_pai`.
+    pub fn new(name: &'static str, min_cap: u32, max_cap: u32, min_pai: u32, max_pai: u32) -> Self {
+        EmulatedPowercapDomain {
+            name,
+            cap: max_cap,
+            min_cap,
+            max_cap,
+            pai: max_pai,
+            min_pai,
+            max_pai,
+        }
+    }
+}
+
+/// Stateful SCMI platform.
+///
+/// The platform is described by filling in the resources, the index of an
+/// entry being its SCMI identifier. Commands update them in place, and
+/// tests can read and change them between commands, e.g. to move a sensor
+/// reading.
+#[derive(Debug, Clone, Default)]
+pub struct Emulator {
+    pub vendor: &'static str,
+    pub sub_vendor: &'static str,
+    pub implementation_version: u32,
+    pub clocks: Vec<EmulatedClock>,
+    pub perf_domains: Vec<EmulatedPerfDomain>,
+    pub sensors: Vec<EmulatedSensor>,
+    pub power_domains: Vec<EmulatedPowerDomain>,
+    pub powercap_domains: Vec<EmulatedPowercapDomain>,
+}
+
+impl Emulator {
+    pub const BASE_VERSION: (u16, u16) = (2, 0);
+    pub const POWER_VERSION: (u16, u16) = (2, 0);
+    pub const PERF_VERSION: (u16, u16) = (2, 0);
+    pub const CLOCK_VERSION: (u16, u16) = (2, 0);
+    pub const SENSOR_VERSION: (u16, u16) = (1, 0);
+    pub const POWERCAP_VERSION: (u16, u16) = (1, 0);
+
+    fn base(&mut self, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, ScmiError> {
+        const PROTOCOLS: [u8; 5] = [POWER, PERF, CLOCK, SENSOR, POWERCAP];
+        match msg_id {
+            PROTOCOL_VERSION => encode_version(Self::BASE_VERSION),
+            // One agent.
+            PROTOCOL_ATTRIBUTES => encode((1u32 << 8) | PROTOCOLS.len() as u32),
+            PROTOCOL_MESSAGE_ATTRIBUTES => message_attributes(
+                req,
+                &[
+                    BASE_DISCOVER_VENDOR,
+                    BASE_DISCOVER_SUB_VENDOR,
+                    BASE_DISCOVER_IMPLEMENTATION_VERSION,
+                    BASE_DISCOVER_LIST_PROTOCOLS,
+                ],
+            ),
+            BASE_DISCOVER_VENDOR => encode(name16(self.vendor)),
+            BASE_DISCOVER_SUB_VENDOR => encode(name16(self.sub_vendor)),
+            BASE_DISCOVER_IMPLEMENTATION_VERSION => encode(self.implementation_version),
+            BASE_DISCOVER_LIST_PROTOCOLS => {
+                let skip: u32 = codec::decode(req)?;
+                let list = PROTOCOLS
+                    .get(skip as usize..)
+                    .ok_or(ScmiError::InvalidParameters)?;
+                let mut resp = encode(list.len() as u32)?;
+                resp.extend_from_slice(list);
+                resp.resize(resp.len().next_multiple_of(size_of::<u32>()), 0);
+                Ok(resp)
+            }
+            _ => Err(ScmiError::NotSupported),
+        }
+    }
+
+    fn power(&mut self, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, ScmiError> {
+        match msg_id {
+            PROTOCOL_VERSION => encode_version(Self::POWER_VERSION),
+            PROTOCOL_ATTRIBUTES => {
+                // No statistics area.
+                let mut resp = encode(self.power_domains.len() as u32)?;
+                resp.resize(4 * size_of::<u32>(), 0);
+                Ok(resp)
+            }
+            PROTOCOL_MESSAGE_ATTRIBUTES => message_attributes(
+                req,
+                &[POWER_DOMAIN_ATTRIBUTES, POWER_STATE_SET, POWER_STATE_GET],
+            ),
+            POWER_DOMAIN_ATTRIBUTES => {
+                let domain = lookup(&self.power_domains, codec::decode(req)?)?;
+                let mut resp = encode(POWER_SYNC_STATE_SET)?;
+                name16(domain.name).encode(&mut Writer::new(&mut resp))?;
+                Ok(resp)
+            }
+            POWER_STATE_SET => {
+                let req: PowerStateSet = codec::decode(req)?;
+                if req.flags & ASYNC_FLAG != 0 {
+                    return Err(ScmiError::NotSupported);
+                }
+                lookup_mut(&mut self.power_domains, req.domain_id)?.state = req.state;
+                Ok(Vec::new())
+            }
+            POWER_STATE_GET => {
+                let domain = lookup(&self.power_domains, codec::decode(req)?)?;
+                encode(domain.state)
+            }
+            _ => Err(ScmiError::NotSupported),
+        }
+    }
+
+    fn perf(&mut self, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, ScmiError> {
+        match msg_id {
+            PROTOCOL_VERSION => encode_version(Self::PERF_VERSION),
+            PROTOCOL_ATTRIBUTES => {
+                let mut resp = encode(self.perf_domains.len() as u32)?;
+                resp.resize(4 * size_of::<u32>(), 0);
+                Ok(resp)
+            }
+            PROTOCOL_MESSAGE_ATTRIBUTES => message_attributes(
+                req,
+                &[
+                    PERF_DOMAIN_ATTRIBUTES,
+                    PERF_LIMITS_SET,
+                    PERF_LIMITS_GET,
+                    PERF_LEVEL_SET,
+                    PERF_LEVEL_GET,
+                ],
+            ),
+            PERF_DOMAIN_ATTRIBUTES => {
+                let domain = lookup(&self.perf_domains, codec::decode(req)?)?;
+                // No rate limit nor sustained performance point, and no
+                // fastchannels.
+                let mut resp = encode(PERF_SET_LIMITS | PERF_SET_LEVEL)?;
+                resp.resize(4 * size_of::<u32>(), 0);
+                name16(domain.name).encode(&mut Writer::new(&mut resp))?;
+                Ok(resp)
+            }
+            PERF_LIMITS_SET => {
+                let req: PerfLimitsSet = codec::decode(req)?;
+                if req.min_level > req.max_level {
+                    return Err(ScmiError::InvalidParameters);
+                }
+                let domain = lookup_mut(&mut self.perf_domains, req.domain_id)?;
+                domain.min_level = req.min_level;
+                domain.max_level = req.max_level;
+                domain.level = domain.level.clamp(req.min_level, req.max_level);
+                Ok(Vec::new())
+            }
+            PERF_LIMITS_GET => {
+                let domain = lookup(&self.perf_domains, codec::decode(req)?)?;
+                encode(PerfLimits {
+                    max_level: domain.max_level,
+                    min_level: domain.min_level,
+                })
+            }
+            PERF_LEVEL_SET => {
+                let req: PerfLevelSet = codec::decode(req)?;
+                let domain = lookup_mut(&mut self.perf_domains, req.domain_id)?;
+                if !(domain.min_level..=domain.max_level).contains(&req.level) {
+                    return Err(ScmiError::OutOfRange);
+                }
+                domain.level = req.level;
+                Ok(Vec::new())
+            }
+            PERF_LEVEL_GET => {
+                let domain = lookup(&self.perf_domains, codec::decode(req)?)?;
+                encode(domain.level)
+            }
+            _ => Err(ScmiError::NotSupported),
+        }
+    }
+
+    fn clock(&mut self, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, ScmiError> {
+        match msg_id {
+            PROTOCOL_VERSION => encode_version(Self::CLOCK_VERSION),
+            // Number of clocks, no asynchronous rate changes.
+            PROTOCOL_ATTRIBUTES => encode(self.clocks.len() as u32),
+            PROTOCOL_MESSAGE_ATTRIBUTES => message_attributes(
+                req,
+                &[
+                    CLOCK_ATTRIBUTES,
+                    CLOCK_RATE_SET,
+                    CLOCK_RATE_GET,
+                    CLOCK_CONFIG_SET,
+                ],
+            ),
+            CLOCK_ATTRIBUTES => {
+                let clock = lookup(&self.clocks, codec::decode(req)?)?;
+                let mut resp = encode(if clock.enabled { CLOCK_ENABLE } else { 0 })?;
+                name16(clock.name).encode(&mut Writer::new(&mut resp))?;
+                Ok(resp)
+            }
+            CLOCK_RATE_SET => {
+                let req: ClockRateSet = codec::decode(req)?;
+                if req.flags & ASYNC_FLAG != 0 {
+                    return Err(ScmiError::NotSupported);
+                }
+                lookup_mut(&mut self.clocks, req.clock_id)?.rate = req.rate;
+                Ok(Vec::new())
+            }
+            CLOCK_RATE_GET => {
+                let clock = lookup(&self.clocks, codec::decode(req)?)?;
+                encode(clock.rate)
+            }
+            CLOCK_CONFIG_SET => {
+                let req: ClockConfigSet = codec::decode(req)?;
+                lookup_mut(&mut self.clocks, req.clock_id)?.enabled =
+                    req.attributes & CLOCK_ENABLE != 0;
+                Ok(Vec::new())
+            }
+            _ => Err(ScmiError::NotSupported),
+        }
+    }
+
+    fn sensor(&mut self, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, ScmiError> {
+        match msg_id {
+            PROTOCOL_VERSION => encode_version(Self::SENSOR_VERSION),
+            PROTOCOL_ATTRIBUTES => {
+                // Number of sensors, no asynchronous reads and no shared
+                // memory for readings.
+                let mut resp = encode(self.sensors.len() as u32)?;
+                resp.resize(4 * size_of::<u32>(), 0);
+                Ok(resp)
+            }
+            PROTOCOL_MESSAGE_ATTRIBUTES => {
+                message_attributes(req, &[SENSOR_DESCRIPTION_GET, SENSOR_READING_GET])
+            }
+            SENSOR_DESCRIPTION_GET => {
+                let first: u32 = codec::decode(req)?;
+                let descs = self
+                    .sensors
+                    .get(first as usize..)
+                    .ok_or(ScmiError::InvalidParameters)?;
+                let returned = descs.len().min(SENSOR_DESCS_PER_MSG);
+                let remaining = descs.len() - returned;
+                let mut resp = encode(((remaining as u32) << 16) | returned as u32)?;
+                for (i, sensor) in descs[..returned].iter().enumerate() {
+                    let mut writer = Writer::new(&mut resp);
+                    (first + i as u32).encode(&mut writer)?;
+                    0u32.encode(&mut writer)?;
+                    (sensor.sensor_type as u32).encode(&mut writer)?;
+                    name16(sensor.name).encode(&mut writer)?;
+                }
+                Ok(resp)
+            }
+            SENSOR_READING_GET => {
+                let req: SensorReadingGet = codec::decode(req)?;
+                if req.flags & ASYNC_FLAG != 0 {
+                    return Err(ScmiError::NotSupported);
+                }
+                let sensor = lookup(&self.sensors, req.sensor_id)?;
+                encode(sensor.value as u64)
+            }
+            _ => Err(ScmiError::NotSupported),
+        }
+    }
+
+    fn powercap(&mut self, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, ScmiError> {
+        match msg_id {
+            PROTOCOL_VERSION => encode_version(Self::POWERCAP_VERSION),
+            PROTOCOL_ATTRIBUTES => encode(self.powercap_domains.len() as u32),
+            PROTOCOL_MESSAGE_ATTRIBUTES => message_attributes(
+                req,
+                &[
+                    POWERCAP_DOMAIN_ATTRIBUTES,
+                    POWERCAP_CAP_GET,
+                    POWERCAP_CAP_SET,
+                    POWERCAP_PAI_GET,
+                    POWERCAP_PAI_SET,
+                ],
+            ),
+            POWERCAP_DOMAIN_ATTRIBUTES => {
+                let domain = lookup(&self.powercap_domains, codec::decode(req)?)?;
+                // Synchronous cap changes, no monitoring, notifications nor
+                // fastchannels, and no parent domain.
+                encode(PowercapDomainAttributes {
+                    attributes: POWERCAP_CAP_CONFIG | POWERCAP_PAI_CONFIG,
+                    name: name16(domain.name),
+                    min_pai: domain.min_pai,
+                    max_pai: domain.max_pai,
+                    pai_step: 1,
+                    min_power_cap: domain.min_cap,
+                    max_power_cap: domain.max_cap,
+                    power_cap_step: 1,
+                    sustainable_power: domain.max_cap,
+                    accuracy: 0,
+                    parent_id: u32::MAX,
+                })
+            }
+            POWERCAP_CAP_GET => {
+                let domain = lookup(&self.powercap_domains, codec::decode(req)?)?;
+                encode(domain.cap)
+            }
+            POWERCAP_CAP_SET => {
+                let req: PowercapValueSet = codec::decode(req)?;
+                if req.flags & ASYNC_FLAG != 0 {
+                    return Err(ScmiError::NotSupported);
+                }
+                let domain = lookup_mut(&mut self.powercap_domains, req.domain_id)?;
+                if !(domain.min_cap..=domain.max_cap).contains(&req.value) {
+                    return Err(ScmiError::OutOfRange);
+                }
+                domain.cap = req.value;
+                Ok(Vec::new())
+            }
+            POWERCAP_PAI_GET => {
+                let domain = lookup(&self.powercap_domains, codec::decode(req)?)?;
+                encode(domain.pai)
+            }
+            POWERCAP_PAI_SET => {
+                let req: PowercapValueSet = codec::decode(req)?;
+                let domain = lookup_mut(&mut self.powercap_domains, req.domain_id)?;
+                if !(domain.min_pai..=domain.max_pai).contains(&req.value) {
+                    return Err(ScmiError::OutOfRange);
+                }
+                domain.pai = req.value;
+                Ok(Vec::new())
+            }
+            _ => Err(ScmiError::NotSupported),
+        }
+    }
+}
+
+impl Platform for Emulator {
+    fn handle(&mut self, protocol_id: u8, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, i32> {
+        let res = match protocol_id {
+            BASE => self.base(msg_id, req),
+            POWER => self.power(msg_id, req),
+            PERF => self.perf(msg_id, req),
+            CLOCK => self.clock(msg_id, req),
+            SENSOR => self.sensor(msg_id, req),
+            POWERCAP => self.powercap(msg_id, req),
+            _ => Err(ScmiError::NotSupported),
+        };
+        res.map_err(|e| {
+            debug!("Emulator failed message {msg_id:#x} of protocol {protocol_id:#x}: {e}");
+            status(e)
+        })
+    }
+}
+
+/// SCMI status code reporting `err`.
+fn status(err: ScmiError) -> i32 {
+    match err {
+        ScmiError::NotSupported => -1,
+        ScmiError::InvalidParameters => -2,
+        ScmiError::AccessDenied => -3,
+        ScmiError::NotFound => -4,
+        ScmiError::OutOfRange => -5,
+        ScmiError::Busy => -6,
+        ScmiError::CommunicationError => -7,
+        ScmiError::HardwareError => -9,
+        ScmiError::ProtocolError => -10,
+        ScmiError::Unknown(status) => status,
+        _ => -8,
+    }
+}
+
+/// Answers `PROTOCOL_MESSAGE_ATTRIBUTES` for a protocol implementing the
+/// generic messages and `msgs`.
+fn message_attributes(req: &[u8], msgs: &[u8]) -> Result<Vec<u8>, ScmiError> {
+    let msg_id: u32 = codec::decode(req)?;
+    let generic = msg_id <= PROTOCOL_MESSAGE_ATTRIBUTES as u32;
+    if !generic && !msgs.iter().any(|&id| id as u32 == msg_id) {
+        return Err(ScmiError::NotFound);
+    }
+    encode(0u32)
+}
+
+fn lookup<R>(resources: &[R], id: u32) -> Result<&R, ScmiError> {
+    resources.get(id as usize).ok_or(ScmiError::NotFound)
+}
+
+fn lookup_mut<R>(resources: &mut [R], id: u32) -> Result<&mut R, ScmiError> {
+    resources.get_mut(id as usize).ok_or(ScmiError::NotFound)
+}
+
+fn encode<E: Encode>(msg: E) -> Result<Vec<u8>, ScmiError> {
+    let mut buf = Vec::new();
+    msg.encode(&mut Writer::new(&mut buf))?;
+    Ok(buf)
+}
+
+fn encode_version(version: (u16, u16)) -> Result<Vec<u8>, ScmiError> {
+    encode(((version.0 as u32) << 16) | version.1 as u32)
+}
+
+/// NUL padded 16 byte name, truncated if longer.
+fn name16(name: &str) -> [u8; 16] {
+    let mut buf = [0; 16];
+    let len = name.len().min(buf.len() - 1);
+    buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{Loopback, Perf, Powercap, Scmi};
+
+    fn platform() -> Loopback<Emulator> {
+        Loopback::new(Emulator {
+            vendor: "emulator",
+            clocks: vec![
+                EmulatedClock::new("ref", 24_000_000),
+                EmulatedClock::new("cpu", 1_200_000_000),
+            ],
+            perf_domains: vec![EmulatedPerfDomain::new("big", 1, 10)],
+            sensors: vec![EmulatedSensor {
+                name: "soc",
+                sensor_type: 2,
+                value: 45,
+            }],
+            power_domains: vec![EmulatedPowerDomain {
+                name: "gpu",
+                state: 0x4000_0000,
+            }],
+            powercap_domains: vec![EmulatedPowercapDomain::new(
+                "soc", 1_000, 15_000, 100, 10_000,
+            )],
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn enumerate_protocols() {
+        let scmi = Scmi::new(platform()).unwrap();
+        let vendor = scmi.raw_xfer(BASE, BASE_DISCOVER_VENDOR, &[], 16).unwrap();
+        assert_eq!(&vendor[..9], b"emulator\0");
+
+        let list = scmi
+            .raw_xfer(BASE, BASE_DISCOVER_LIST_PROTOCOLS, &0u32.to_le_bytes(), 12)
+            .unwrap();
+        assert_eq!(
+            list,
+            [5, 0, 0, 0, POWER, PERF, CLOCK, SENSOR, POWERCAP, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn clock_bring_up() {
+        let transport = platform();
+        let scmi = Scmi::new(transport.clone()).unwrap();
+        let mut clk = scmi.protocol_clk().unwrap();
+        assert_eq!(clk.num_clocks(), 2);
+
+        clk.clk_enable(1).unwrap();
+        clk.rate_set(1, 1_800_000_000).unwrap();
+        assert_eq!(clk.rate_get(1), Ok(1_800_000_000));
+        assert!(transport.platform().clocks[1].enabled);
+        assert!(!transport.platform().clocks[0].enabled);
+
+        assert_eq!(clk.rate_get(2), Err(ScmiError::NotFound));
+    }
+
+    #[test]
+    fn perf_levels_follow_limits() {
+        let transport = platform();
+        let scmi = Scmi::new(transport.clone()).unwrap();
+        let mut perf = scmi.protocol_perf().unwrap();
+        assert_eq!(perf.num_domains(), 1);
+
+        perf.level_set(0, 8).unwrap();
+        perf.limits_set(0, 6, 2).unwrap();
+        assert_eq!(perf.limits_get(0), Ok((6, 2)));
+        assert_eq!(perf.level_get(0), Ok(6));
+        assert_eq!(perf.level_set(0, 7), Err(ScmiError::OutOfRange));
+        assert_eq!(
+            perf.describe_fastchannel(0, Perf::<Loopback<Emulator>>::LEVEL_SET),
+            Err(ScmiError::NotSupported)
+        );
+    }
+
+    #[test]
+    fn powercap_follows_limits() {
+        let transport = platform();
+        let scmi = Scmi::new(transport.clone()).unwrap();
+        let mut powercap = scmi.protocol_powercap().unwrap();
+        assert_eq!(powercap.num_domains(), 1);
+
+        assert_eq!(powercap.cap_get(0), Ok(15_000));
+        powercap.cap_set(0, 5_000).unwrap();
+        assert_eq!(powercap.cap_get(0), Ok(5_000));
+        assert_eq!(transport.platform().powercap_domains[0].cap, 5_000);
+        assert_eq!(powercap.cap_set(0, 20_000), Err(ScmiError::OutOfRange));
+
+        powercap.pai_set(0, 1_000).unwrap();
+        assert_eq!(powercap.pai_get(0), Ok(1_000));
+        assert_eq!(powercap.pai_set(0, 10), Err(ScmiError::OutOfRange));
+        assert_eq!(powercap.cap_get(1), Err(ScmiError::NotFound));
+        assert_eq!(
+            powercap.describe_fastchannel(0, Powercap::<Loopback<Emulator>>::CAP_SET),
+            Err(ScmiError::NotSupported)
+        );
+    }
+
+    #[test]
+    fn sensor_and_power_state() {
+        let transport = platform();
+        let scmi = Scmi::new(transport.clone()).unwrap();
+        let read = |scmi: &Scmi<_>| {
+            let req = SensorReadingGet {
+                sensor_id: 0,
+                flags: 0,
+            };
+            let resp = scmi
+                .raw_xfer(SENSOR, SENSOR_READING_GET, &encode(req).unwrap(), 8)
+                .unwrap();
+            i64::from_le_bytes(resp.try_into().unwrap())
+        };
+        assert_eq!(read(&scmi), 45);
+        transport.platform().sensors[0].value = -5;
+        assert_eq!(read(&scmi), -5);
+
+        let on = PowerStateSet {
+            flags: 0,
+            domain_id: 0,
+            state: 0,
+        };
+        scmi.raw_xfer(POWER, POWER_STATE_SET, &encode(on).unwrap(), 0)
+            .unwrap();
+        assert_eq!(transport.platform().power_domains[0].state, 0);
+    }
+}
@@ -20,6 +20,16 @@ pub enum ScmiError {
     HardwareError,
     #[error("Protocol error")]
     ProtocolError,
+    #[error("Transport failure")]
+    TransportFailure,
+    #[error("SMCCC call failed: {0}")]
+    Smccc(SmcccError),
+    #[error("FF-A call failed: {0}")]
+    Ffa(FfaError),
+    #[error("Out of memory")]
+    NoMemory,
+    #[error("Invalid message length {0}")]
+    InvalidLength(usize),
     #[error("Unknown SCMI error {0}")]
     Unknown(i32),
 }
@@ -44,3 +54,117 @@ impl ScmiError {
         }
     }
 }
+
+/// Error returned by the firmware for an SMC/HVC call itself, before any SCMI
+/// message is processed.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmcccError {
+    /// The firmware does not implement the function id.
+    #[error("not supported")]
+    NotSupported,
+    #[error("not required")]
+    NotRequired,
+    #[error("invalid parameter")]
+    InvalidParameter,
+    #[error("unknown return value {0}")]
+    Unknown(i64),
+}
+
+impl SmcccError {
+    pub const NOT_SUPPORTED: i64 = -1;
+    pub const NOT_REQUIRED: i64 = -2;
+    pub const INVALID_PARAMETER: i64 = -3;
+
+    /// Raw value returned in `x0`.
+    pub fn code(&self) -> i64 {
+        match self {
+            SmcccError::NotSupported => Self::NOT_SUPPORTED,
+            SmcccError::NotRequired => Self::NOT_REQUIRED,
+            SmcccError::InvalidParameter => Self::INVALID_PARAMETER,
+            SmcccError::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<i64> for SmcccError {
+    fn from(code: i64) -> Self {
+        match code {
+            Self::NOT_SUPPORTED => SmcccError::NotSupported,
+            Self::NOT_REQUIRED => SmcccError::NotRequired,
+            Self::INVALID_PARAMETER => SmcccError::InvalidParameter,
+            other => SmcccError::Unknown(other),
+        }
+    }
+}
+
+impl From<SmcccError> for ScmiError {
+    fn from(err: SmcccError) -> Self {
+        ScmiError::Smccc(err)
+    }
+}
+
+/// Error code returned by an FF-A `FFA_ERROR` response.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfaError {
+    #[error("not supported")]
+    NotSupported,
+    #[error("invalid parameters")]
+    InvalidParameters,
+    #[error("no memory")]
+    NoMemory,
+    #[error("busy")]
+    Busy,
+    #[error("interrupted")]
+    Interrupted,
+    #[error("denied")]
+    Denied,
+    #[error("retry")]
+    Retry,
+    #[error("aborted")]
+    Aborted,
+    #[error("no data")]
+    NoData,
+    #[error("unknown error {0}")]
+    Unknown(i32),
+}
+
+impl FfaError {
+    /// Raw value returned in `w2`.
+    pub fn code(&self) -> i32 {
+        match self {
+            FfaError::NotSupported => -1,
+            FfaError::InvalidParameters => -2,
+            FfaError::NoMemory => -3,
+            FfaError::Busy => -4,
+            FfaError::Interrupted => -5,
+            FfaError::Denied => -6,
+            FfaError::Retry => -7,
+            FfaError::Aborted => -8,
+            FfaError::NoData => -9,
+            FfaError::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<i32> for FfaError {
+    fn from(code: i32) -> Self {
+        match code {
+            -1 => FfaError::NotSupported,
+            -2 => FfaError::InvalidParameters,
+            -3 => FfaError::NoMemory,
+            -4 => FfaError::Busy,
+            -5 => FfaError::Interrupted,
+            -6 => FfaError::Denied,
+            -7 => FfaError::Retry,
+            -8 => FfaError::Aborted,
+            -9 => FfaError::NoData,
+            other => FfaError::Unknown(other),
+        }
+    }
+}
+
+impl From<FfaError> for ScmiError {
+    fn from(err: FfaError) -> Self {
+        ScmiError::Ffa(err)
+    }
+}
@@ -1,21 +1,37 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
-#[macro_use]
 extern crate alloc;
 #[macro_use]
 extern crate log;
 
-pub use crate::{protocol::Xfer, shmem::Shmem};
+pub use crate::{
+    err::{FfaError, ScmiError, SmcccError},
+    protocol::codec,
+    protocol::fastchannel::{DoorbellWidth, Fastchannel, FastchannelDesc, FastchannelDoorbell},
+    protocol::{
+        Clock, FuturePoll, MsgHeader, MsgType, Notification, Perf, Powercap, Protocal,
+        ScmiProtocol, Xfer, XferFuture, XferPoll, XferStatus,
+    },
+    shmem::{Shmem, ShmemAccess, ShmemLayout},
+};
 
+#[cfg(any(test, feature = "emulator"))]
+pub mod emulator;
 mod err;
 mod protocol;
 mod shmem;
 mod transport;
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
+use nb::block;
 use spin::Mutex;
-pub use transport::Smc;
 pub use transport::Transport;
+pub use transport::{
+    Conduit, Doorbell, Ffa, FfaAbi, FfaSmc, Mailbox, Mhuv2Doorbell, Mhuv3Doorbell, Optee, OpteeAbi,
+    OpteeParam, OpteeSmc, Smc, Virtio,
+};
+#[cfg(any(test, feature = "mock"))]
+pub use transport::{Loopback, MockTransport, Platform};
 
 type Data<T> = Arc<Mutex<ScmiData<T>>>;
 
@@ -24,39 +40,155 @@ pub struct Scmi<T: Transport> {
 }
 
 impl<T: Transport> Scmi<T> {
-    pub fn new(kind: T, mut shmem: Shmem) -> Self {
-        shmem.reset();
+    /// Fails if the transport has no command channel.
+    pub fn new(transport: T) -> Result<Self, ScmiError> {
+        if !transport.chan_available(0) {
+            error!("SCMI transport has no command channel");
+            return Err(ScmiError::TransportFailure);
+        }
         let data = ScmiData {
-            transport: kind,
-            shmem,
+            xfers: protocol::XferTable::new(transport.max_msg()),
+            transport,
         };
-        Scmi {
+        Ok(Scmi {
             data: Arc::new(Mutex::new(data)),
+        })
+    }
+
+    /// Completion interrupt handler.
+    ///
+    /// Call this from the handler of the transport's completion interrupt
+    /// (e.g. the `a2p` interrupt of an SMC channel) to wake the task awaiting
+    /// the answered transfer.
+    pub fn handle_irq(&self) {
+        let wakers = {
+            let mut data = self.data.lock();
+            if data.transport.no_completion_irq() {
+                return;
+            }
+            data.transport.ack_irq();
+            let token = data.transport.completed_token();
+            data.xfers.take_wakers(token)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Reads a notification or delayed response sent by the platform.
+    ///
+    /// Call this when the P2A channel interrupt fires; `None` means the
+    /// transport has no such channel.
+    pub fn fetch_notification(&self) -> Result<Option<Notification>, ScmiError> {
+        let mut data = self.data.lock();
+        if !data.transport.chan_available(1) {
+            return Ok(None);
+        }
+        data.transport.fetch_notification()
+    }
+
+    /// Opens a handle on an arbitrary protocol, including the vendor specific
+    /// range `0x80..=0xFF`.
+    ///
+    /// No discovery is done, the caller is responsible for checking that the
+    /// platform implements the protocol.
+    pub fn open_protocol(&self, protocol_id: u8) -> Protocal<T> {
+        Protocal::new(self.data.clone(), protocol_id)
+    }
+
+    /// Sends a raw message and waits for its response payload.
+    ///
+    /// `tx` is the message payload and `rx_len` the largest response payload
+    /// the caller accepts, status word excluded. Longer responses fail with
+    /// [`ScmiError::InvalidLength`].
+    pub fn raw_xfer(
+        &self,
+        protocol_id: u8,
+        msg_id: u8,
+        tx: &[u8],
+        rx_len: usize,
+    ) -> Result<Vec<u8>, ScmiError> {
+        let mut protocol = self.open_protocol(protocol_id);
+        let mut xfer = protocol.raw_xfer(msg_id, tx, rx_len)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn raw_xfer_async(
+        &self,
+        protocol_id: u8,
+        msg_id: u8,
+        tx: &[u8],
+        rx_len: usize,
+    ) -> Result<Vec<u8>, ScmiError> {
+        let mut protocol = self.open_protocol(protocol_id);
+        protocol.raw_xfer(msg_id, tx, rx_len)?.await
+    }
+
+    /// Opens protocol `P`, checking the version the platform implements and
+    /// running its discovery.
+    pub fn protocol<P: ScmiProtocol<T>>(&self) -> Result<P, ScmiError> {
+        let mut protocol = self.open_protocol(P::PROTOCOL_ID);
+        let version = {
+            let mut xfer = protocol.version()?;
+            block!(xfer.poll_completion())?
+        };
+        if version < P::MIN_VERSION {
+            warn!(
+                "Protocol {:#x} version {}.{} is older than supported {}.{}",
+                P::PROTOCOL_ID,
+                version.0,
+                version.1,
+                P::MIN_VERSION.0,
+                P::MIN_VERSION.1
+            );
+            return Err(ScmiError::NotSupported);
+        }
+        if version > P::MAX_VERSION {
+            warn!(
+                "Protocol {:#x} version {}.{} is newer than supported {}.{}",
+                P::PROTOCOL_ID,
+                version.0,
+                version.1,
+                P::MAX_VERSION.0,
+                P::MAX_VERSION.1
+            );
         }
+        P::init(protocol, version)
     }
 
-    pub fn protocol_clk(&self) -> protocol::Clock<T> {
-        let data = self.data.clone();
-        let mut clk = protocol::Clock::new(protocol::Protocal::new(
-            data,
-            protocol::Clock::<T>::PROTOCOL_ID,
-        ));
-        clk.init();
-        clk
+    pub fn protocol_clk(&self) -> Result<Clock<T>, ScmiError> {
+        self.protocol()
+    }
+
+    pub fn protocol_perf(&self) -> Result<Perf<T>, ScmiError> {
+        self.protocol()
+    }
+
+    pub fn protocol_powercap(&self) -> Result<Powercap<T>, ScmiError> {
+        self.protocol()
     }
 }
 
 struct ScmiData<T: Transport> {
     transport: T,
-    shmem: Shmem,
+    xfers: protocol::XferTable,
 }
 
 impl<T: Transport> ScmiData<T> {
-    pub fn send_message(&mut self, xfer: &mut Xfer) -> Result<(), crate::err::ScmiError> {
-        self.transport.send_message(&mut self.shmem, xfer)
+    pub fn send_message(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        if xfer.tx.len() > self.transport.max_msg_size() {
+            return Err(ScmiError::InvalidLength(xfer.tx.len()));
+        }
+        self.transport.send_message(xfer)
+    }
+
+    /// Whether the answer to `xfer` can be fetched.
+    pub fn response_ready(&mut self, xfer: &Xfer) -> bool {
+        (xfer.hdr.poll_completion && self.transport.sync_cmds_completed_on_ret())
+            || self.transport.poll_done(xfer)
     }
 
-    pub fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), crate::err::ScmiError> {
-        self.transport.fetch_response(&mut self.shmem, xfer)
+    pub fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        self.transport.fetch_response(xfer)
     }
 }
@@ -1,6 +1,10 @@
 use nb::block;
 
-use crate::{Transport, err::ScmiError, protocol::FuturePoll};
+use crate::{
+    Transport,
+    err::ScmiError,
+    protocol::{FuturePoll, Protocal, ScmiProtocol, XferPoll},
+};
 
 const PROTOCOL_RATE_SET: u8 = 0x5;
 const PROTOCOL_RATE_GET: u8 = 0x6;
@@ -8,89 +12,214 @@ const PROTOCOL_CONFIG_SET: u8 = 0x7;
 
 const ATTRIBUTES_CLOCK_ENABLE: u32 = 1 << 0;
 
+crate::scmi_message! {
+    struct ClockAttributes {
+        num_clocks: u16,
+        max_async_req: u8,
+        _reserved: u8,
+    }
+
+    /// The spec splits the rate into low and high words, which is exactly the
+    /// little-endian layout of a `u64`.
+    struct RateSet {
+        flags: u32,
+        clock_id: u32,
+        rate: u64,
+    }
+
+    struct ConfigSet {
+        clock_id: u32,
+        attributes: u32,
+    }
+}
+
 pub struct Clock<T: Transport> {
-    protocol: super::Protocal<T>,
+    protocol: Protocal<T>,
+    version: (u16, u16),
     num_clocks: u16,
     max_async_req: u8,
 }
 
-impl<T: Transport> Clock<T> {
-    pub const PROTOCOL_ID: u8 = 0x14;
+impl<T: Transport> ScmiProtocol<T> for Clock<T> {
+    const PROTOCOL_ID: u8 = 0x14;
+    const MIN_VERSION: (u16, u16) = (1, 0);
+    const MAX_VERSION: (u16, u16) = (3, 0);
 
-    pub(crate) fn new(protocol: super::Protocal<T>) -> Self {
-        Self {
+    fn init(protocol: Protocal<T>, version: (u16, u16)) -> Result<Self, ScmiError> {
+        debug!("Clock Protocol version: {}.{}", version.0, version.1);
+        let mut clk = Self {
             protocol,
+            version,
             num_clocks: 0,
             max_async_req: 0,
-        }
+        };
+        clk.attributes()?;
+        Ok(clk)
+    }
+}
+
+impl<T: Transport> Clock<T> {
+    /// Protocol version reported by the platform.
+    pub fn version(&self) -> (u16, u16) {
+        self.version
     }
 
-    pub(crate) fn init(&mut self) {
-        {
-            // Initialization code if needed
-            let mut version_fur = self.protocol.version();
-            let version = block!(version_fur.poll_completion()).unwrap();
-            debug!("Clock Protocol version: {}.{}", version.0, version.1);
-        }
-        self.attributes().unwrap();
+    pub fn num_clocks(&self) -> u16 {
+        self.num_clocks
     }
 
     fn attributes(&mut self) -> Result<(), ScmiError> {
-        let xfer = super::Xfer::new(super::PROTOCOL_ATTRIBUTES, 4);
-        let mut res = self.protocol.do_xfer(xfer, |xfer| {
-            let mut buff = [0u8; 4];
-            buff[..4].copy_from_slice(&xfer.rx[..4]);
-            Ok(buff)
-        });
+        let mut res = self
+            .protocol
+            .xfer::<(), ClockAttributes>(super::PROTOCOL_ATTRIBUTES, ())?;
         let res = block!(res.poll_completion())?;
-        let num_clocks = u16::from_le_bytes([res[0], res[1]]);
-        let max_async_req = res[2];
-        self.max_async_req = max_async_req;
-        self.num_clocks = num_clocks;
+        self.max_async_req = res.max_async_req;
+        self.num_clocks = res.num_clocks;
         debug!(
             "Clock Protocol Attributes: num_clocks={}, max_async_req={}",
-            num_clocks, max_async_req
+            res.num_clocks, res.max_async_req
         );
         Ok(())
     }
 
     pub fn clk_enable(&mut self, clk_id: u32) -> Result<(), ScmiError> {
-        self.clock_config_set(clk_id, ATTRIBUTES_CLOCK_ENABLE)
+        let mut xfer = self.clock_config_set(clk_id, ATTRIBUTES_CLOCK_ENABLE)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn clk_enable_async(&mut self, clk_id: u32) -> Result<(), ScmiError> {
+        self.clock_config_set(clk_id, ATTRIBUTES_CLOCK_ENABLE)?
+            .await
     }
 
     pub fn clk_disable(&mut self, clk_id: u32) -> Result<(), ScmiError> {
-        self.clock_config_set(clk_id, 0)
+        let mut xfer = self.clock_config_set(clk_id, 0)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn clk_disable_async(&mut self, clk_id: u32) -> Result<(), ScmiError> {
+        self.clock_config_set(clk_id, 0)?.await
     }
 
     pub fn rate_get(&mut self, clk_id: u32) -> Result<u64, ScmiError> {
-        let mut xfer = super::Xfer::new(PROTOCOL_RATE_GET, size_of::<u64>());
-        xfer.tx.extend_from_slice(&clk_id.to_le_bytes());
-        let mut res = self.protocol.do_xfer(xfer, |xfer| {
-            let mut buff = [0u8; 8];
-            buff.copy_from_slice(&xfer.rx[..8]);
-            Ok(u64::from_le_bytes(buff))
-        });
-        block!(res.poll_completion())
+        let mut xfer = self.xfer_rate_get(clk_id)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn rate_get_async(&mut self, clk_id: u32) -> Result<u64, ScmiError> {
+        self.xfer_rate_get(clk_id)?.await
     }
 
     pub fn rate_set(&mut self, clk_id: u32, rate: u64) -> Result<(), ScmiError> {
-        let mut xfer = super::Xfer::new(PROTOCOL_RATE_SET, 12);
-        let flags = 0u32;
-        xfer.tx.extend_from_slice(&flags.to_le_bytes());
-        xfer.tx.extend_from_slice(&clk_id.to_le_bytes());
-        xfer.tx
-            .extend_from_slice(((rate & 0xffffffff) as u32).to_le_bytes().as_slice());
-        xfer.tx
-            .extend_from_slice(((rate >> 32) as u32).to_le_bytes().as_slice());
-        let mut res = self.protocol.do_xfer(xfer, |_xfer| Ok(()));
-        block!(res.poll_completion())
-    }
-
-    fn clock_config_set(&mut self, clk_id: u32, config: u32) -> Result<(), ScmiError> {
-        let mut xfer = super::Xfer::new(PROTOCOL_CONFIG_SET, 0);
-        xfer.tx.extend_from_slice(&clk_id.to_le_bytes());
-        xfer.tx.extend_from_slice(&config.to_le_bytes());
-        let mut res = self.protocol.do_xfer(xfer, |_xfer| Ok(()));
-        block!(res.poll_completion())
+        let mut xfer = self.xfer_rate_set(clk_id, rate)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn rate_set_async(&mut self, clk_id: u32, rate: u64) -> Result<(), ScmiError> {
+        self.xfer_rate_set(clk_id, rate)?.await
+    }
+
+    fn xfer_rate_get(&mut self, clk_id: u32) -> Result<impl XferPoll<u64> + '_, ScmiError> {
+        self.protocol.xfer(PROTOCOL_RATE_GET, clk_id)
+    }
+
+    fn xfer_rate_set(
+        &mut self,
+        clk_id: u32,
+        rate: u64,
+    ) -> Result<impl XferPoll<()> + '_, ScmiError> {
+        let req = RateSet {
+            flags: 0,
+            clock_id: clk_id,
+            rate,
+        };
+        self.protocol.xfer(PROTOCOL_RATE_SET, req)
+    }
+
+    fn clock_config_set(
+        &mut self,
+        clk_id: u32,
+        config: u32,
+    ) -> Result<impl XferPoll<()> + '_, ScmiError> {
+        let req = ConfigSet {
+            clock_id: clk_id,
+            attributes: config,
+        };
+        self.protocol.xfer(PROTOCOL_CONFIG_SET, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockTransport, Scmi};
+
+    const CLOCK: u8 = 0x14;
+
+    fn open(mock: &MockTransport) -> Clock<MockTransport> {
+        mock.expect_version(CLOCK, (2, 0)).expect(
+            CLOCK,
+            super::super::PROTOCOL_ATTRIBUTES,
+            (),
+            [4, 0, 1, 0],
+        );
+        Scmi::new(mock.clone()).unwrap().protocol_clk().unwrap()
+    }
+
+    #[test]
+    fn discovery() {
+        let mock = MockTransport::new();
+        let clk = open(&mock);
+        assert_eq!(clk.version(), (2, 0));
+        assert_eq!(clk.num_clocks(), 4);
+        assert_eq!(mock.pending(), 0);
+    }
+
+    #[test]
+    fn rate_get_and_set() {
+        let mock = MockTransport::new();
+        let mut clk = open(&mock);
+        mock.expect(CLOCK, PROTOCOL_RATE_GET, 2u32, 24_000_000u64);
+        assert_eq!(clk.rate_get(2), Ok(24_000_000));
+
+        let req = RateSet {
+            flags: 0,
+            clock_id: 2,
+            rate: 1_800_000_000,
+        };
+        mock.expect(CLOCK, PROTOCOL_RATE_SET, req, ());
+        assert_eq!(clk.rate_set(2, 1_800_000_000), Ok(()));
+        assert_eq!(mock.pending(), 0);
+    }
+
+    #[test]
+    fn enable_denied() {
+        let mock = MockTransport::new();
+        let mut clk = open(&mock);
+        let req = ConfigSet {
+            clock_id: 1,
+            attributes: ATTRIBUTES_CLOCK_ENABLE,
+        };
+        mock.expect_status(CLOCK, PROTOCOL_CONFIG_SET, req, -3);
+        assert_eq!(clk.clk_enable(1), Err(ScmiError::AccessDenied));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mock = MockTransport::new();
+        mock.expect_version(CLOCK, (0, 1));
+        let res = Scmi::new(mock.clone()).unwrap().protocol_clk();
+        assert_eq!(res.err(), Some(ScmiError::NotSupported));
+    }
+
+    #[test]
+    fn longer_reply_is_accepted() {
+        let mock = MockTransport::new();
+        let mut clk = open(&mock);
+        // A newer platform appending a field to the rate.
+        let mut reply = [0u8; 12];
+        reply[..8].copy_from_slice(&24_000_000u64.to_le_bytes());
+        mock.expect(CLOCK, PROTOCOL_RATE_GET, 2u32, reply);
+        assert_eq!(clk.rate_get(2), Ok(24_000_000));
     }
 }
@@ -0,0 +1,267 @@
+//! Little-endian encoding of SCMI message payloads.
+//!
+//! Requests implement [`Encode`] and responses [`Decode`]. Decoding never
+//! panics: a reply shorter than expected is reported as
+//! [`ScmiError::ProtocolError`]. Message structs are usually declared with
+//! [`scmi_message!`](crate::scmi_message), which derives both traits from
+//! the field list.
+
+use alloc::vec::Vec;
+
+use crate::err::ScmiError;
+
+pub trait Encode {
+    /// Fails with [`ScmiError::NoMemory`] if the payload cannot grow.
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), ScmiError>;
+}
+
+pub trait Decode: Sized {
+    /// Encoded size in bytes, used to size the receive buffer.
+    const SIZE: usize;
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ScmiError>;
+}
+
+/// Appends encoded fields to a request payload.
+pub struct Writer<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut Vec<u8>) -> Self {
+        Self { buf }
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) -> Result<(), ScmiError> {
+        self.buf
+            .try_reserve(data.len())
+            .map_err(|_| ScmiError::NoMemory)?;
+        self.buf.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Bounds checked cursor over a response payload.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// Consumes the next `len` bytes.
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], ScmiError> {
+        if self.buf.len() < len {
+            warn!(
+                "Truncated SCMI payload: need {len} bytes, {} left",
+                self.buf.len()
+            );
+            return Err(ScmiError::ProtocolError);
+        }
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], ScmiError> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.bytes(N)?);
+        Ok(out)
+    }
+
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+/// Decodes a whole response payload as `R`.
+///
+/// Trailing bytes, which newer platforms may append, are ignored.
+pub fn decode<R: Decode>(buf: &[u8]) -> Result<R, ScmiError> {
+    let mut reader = Reader::new(buf);
+    let res = R::decode(&mut reader)?;
+    if !reader.remaining().is_empty() {
+        trace!("Ignoring {} trailing bytes", reader.remaining().len());
+    }
+    Ok(res)
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode(&self, writer: &mut Writer<'_>) -> Result<(), ScmiError> {
+                    writer.bytes(&self.to_le_bytes())
+                }
+            }
+
+            impl Decode for $t {
+                const SIZE: usize = size_of::<$t>();
+
+                fn decode(reader: &mut Reader<'_>) -> Result<Self, ScmiError> {
+                    reader.array().map(<$t>::from_le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_int!(u8, u16, u32, u64, i32);
+
+impl Encode for () {
+    fn encode(&self, _writer: &mut Writer<'_>) -> Result<(), ScmiError> {
+        Ok(())
+    }
+}
+
+impl Decode for () {
+    const SIZE: usize = 0;
+
+    fn decode(_reader: &mut Reader<'_>) -> Result<Self, ScmiError> {
+        Ok(())
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, writer: &mut Writer<'_>) -> Result<(), ScmiError> {
+        writer.bytes(self)
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    const SIZE: usize = N;
+
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, ScmiError> {
+        reader.array()
+    }
+}
+
+/// Declares SCMI message structs with [`Encode`] and [`Decode`]
+/// implementations encoding the fields in declaration order.
+///
+/// ```ignore
+/// arm_scmi::scmi_message! {
+///     pub struct RateSet {
+///         pub flags: u32,
+///         pub clock_id: u32,
+///         pub rate: u64,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! scmi_message {
+    ($(
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$fmeta:meta])* $fvis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    )*) => {$(
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$fmeta])* $fvis $field: $ty),*
+        }
+
+        impl $crate::codec::Encode for $name {
+            fn encode(
+                &self,
+                writer: &mut $crate::codec::Writer<'_>,
+            ) -> ::core::result::Result<(), $crate::ScmiError> {
+                $($crate::codec::Encode::encode(&self.$field, writer)?;)*
+                Ok(())
+            }
+        }
+
+        impl $crate::codec::Decode for $name {
+            const SIZE: usize = 0 $(+ <$ty as $crate::codec::Decode>::SIZE)*;
+
+            fn decode(
+                reader: &mut $crate::codec::Reader<'_>,
+            ) -> ::core::result::Result<Self, $crate::ScmiError> {
+                Ok(Self {
+                    $($field: $crate::codec::Decode::decode(reader)?),*
+                })
+            }
+        }
+    )*};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::scmi_message! {
+        struct Rate {
+            clock_id: u32,
+            rate: u64,
+        }
+
+        struct Named {
+            attributes: u32,
+            name: [u8; 16],
+        }
+    }
+
+    fn encode(value: &dyn Encode) -> Vec<u8> {
+        let mut buf = Vec::new();
+        value.encode(&mut Writer::new(&mut buf)).unwrap();
+        buf
+    }
+
+    #[test]
+    fn short_reply_is_protocol_error() {
+        assert_eq!(Rate::SIZE, 12);
+        assert!(matches!(
+            decode::<Rate>(&[0; 11]),
+            Err(ScmiError::ProtocolError)
+        ));
+        assert_eq!(decode::<u32>(&[1, 2]), Err(ScmiError::ProtocolError));
+        assert_eq!(decode::<()>(&[]), Ok(()));
+    }
+
+    #[test]
+    fn u64_is_low_then_high_word() {
+        let rate = Rate {
+            clock_id: 7,
+            rate: 0x1_2345_6789,
+        };
+        let buf = encode(&rate);
+        assert_eq!(
+            buf,
+            [7, 0, 0, 0, 0x89, 0x67, 0x45, 0x23, 0x01, 0, 0, 0],
+            "clock id, rate low word, rate high word"
+        );
+
+        let decoded: Rate = decode(&buf).unwrap();
+        assert_eq!(decoded.clock_id, 7);
+        assert_eq!(decoded.rate, 0x1_2345_6789);
+    }
+
+    #[test]
+    fn names_are_raw_bytes() {
+        let mut name = [0u8; 16];
+        name[..6].copy_from_slice(b"cpu0-a");
+        let named = Named {
+            attributes: 1 << 31,
+            name,
+        };
+        let buf = encode(&named);
+        assert_eq!(Named::SIZE, 20);
+        assert_eq!(buf.len(), 20);
+        assert_eq!(&buf[4..10], b"cpu0-a");
+
+        let decoded: Named = decode(&buf).unwrap();
+        assert_eq!(decoded.attributes, 1 << 31);
+        assert_eq!(decoded.name, name);
+        assert!(matches!(
+            decode::<Named>(&buf[..19]),
+            Err(ScmiError::ProtocolError)
+        ));
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        assert_eq!(decode::<u32>(&[1, 0, 0, 0, 0xff, 0xff]), Ok(1));
+    }
+}
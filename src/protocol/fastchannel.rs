@@ -0,0 +1,361 @@
+//! Fastchannels let the agent read and write some performance and power
+//! capping values in memory shared with the platform, without sending a
+//! message.
+//!
+//! [`Perf`](super::Perf) and [`Powercap`](super::Powercap) describe them
+//! through `DESCRIBE_FASTCHANNEL`. The returned physical addresses must be
+//! mapped by the caller before building a [`Fastchannel`].
+
+use core::ptr::NonNull;
+
+use mbarrier::{rmb, wmb};
+use nb::block;
+
+use crate::{
+    Transport,
+    err::ScmiError,
+    protocol::{FuturePoll, Protocal},
+};
+
+const ATTR_DOORBELL: u32 = 1 << 0;
+const ATTR_DOORBELL_WIDTH_SHIFT: u32 = 1;
+const ATTR_DOORBELL_WIDTH_MASK: u32 = 0x3;
+
+crate::scmi_message! {
+    struct DescribeFastchannel {
+        domain_id: u32,
+        message_id: u32,
+    }
+
+    struct DescribeFastchannelResp {
+        attributes: u32,
+        rate_limit: u32,
+        chan_addr: u64,
+        chan_size: u32,
+        db_addr: u64,
+        db_set_mask: u64,
+        db_preserve_mask: u64,
+    }
+}
+
+/// Width of a fastchannel doorbell register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoorbellWidth {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl DoorbellWidth {
+    fn bytes(self) -> usize {
+        match self {
+            DoorbellWidth::U8 => 1,
+            DoorbellWidth::U16 => 2,
+            DoorbellWidth::U32 => 4,
+            DoorbellWidth::U64 => 8,
+        }
+    }
+}
+
+/// Doorbell to ring after writing a fastchannel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastchannelDoorbell {
+    /// Physical address of the doorbell register.
+    pub addr: u64,
+    pub width: DoorbellWidth,
+    /// Bits to set when ringing.
+    pub set_mask: u64,
+    /// Bits of the current register value to keep when ringing.
+    pub preserve_mask: u64,
+}
+
+/// Fastchannel as described by the platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastchannelDesc {
+    /// Physical address of the channel.
+    pub chan_addr: u64,
+    pub chan_size: u32,
+    /// Minimum interval between updates, in microseconds.
+    pub rate_limit_us: u32,
+    pub doorbell: Option<FastchannelDoorbell>,
+}
+
+/// Sends `DESCRIBE_FASTCHANNEL` (`describe_id` in the calling protocol) for
+/// `message_id` of `domain_id`.
+pub(crate) fn describe<T: Transport>(
+    protocol: &mut Protocal<T>,
+    describe_id: u8,
+    domain_id: u32,
+    message_id: u8,
+) -> Result<FastchannelDesc, ScmiError> {
+    let req = DescribeFastchannel {
+        domain_id,
+        message_id: message_id as u32,
+    };
+    let mut xfer = protocol.xfer::<_, DescribeFastchannelResp>(describe_id, req)?;
+    let res = block!(xfer.poll_completion())?;
+
+    let doorbell = if res.attributes & ATTR_DOORBELL != 0 {
+        let width = match (res.attributes >> ATTR_DOORBELL_WIDTH_SHIFT) & ATTR_DOORBELL_WIDTH_MASK {
+            0 => DoorbellWidth::U8,
+            1 => DoorbellWidth::U16,
+            2 => DoorbellWidth::U32,
+            _ => DoorbellWidth::U64,
+        };
+        Some(FastchannelDoorbell {
+            addr: res.db_addr,
+            width,
+            set_mask: res.db_set_mask,
+            preserve_mask: res.db_preserve_mask,
+        })
+    } else {
+        None
+    };
+    let desc = FastchannelDesc {
+        chan_addr: res.chan_addr,
+        chan_size: res.chan_size,
+        rate_limit_us: res.rate_limit,
+        doorbell,
+    };
+    debug!(
+        "Fastchannel for message {message_id:#x} of domain {domain_id}: {:?}",
+        desc
+    );
+    Ok(desc)
+}
+
+/// A mapped fastchannel.
+pub struct Fastchannel {
+    chan: NonNull<u32>,
+    words: usize,
+    rate_limit_us: u32,
+    doorbell: Option<(NonNull<u8>, FastchannelDoorbell)>,
+}
+
+impl Fastchannel {
+    /// Builds the accessor for `desc`.
+    ///
+    /// # Safety
+    ///
+    /// `chan` must map `desc.chan_addr` for `desc.chan_size` bytes, and
+    /// `doorbell` the doorbell register when `desc` has one. Both mappings
+    /// must outlive the accessor.
+    ///
+    /// Fails with [`ScmiError::InvalidParameters`] if the channel is not word
+    /// aligned or shorter than a word, or if the doorbell is not aligned to
+    /// its width.
+    pub unsafe fn new(
+        desc: &FastchannelDesc,
+        chan: NonNull<u8>,
+        doorbell: Option<NonNull<u8>>,
+    ) -> Result<Self, ScmiError> {
+        if !chan.cast::<u32>().is_aligned() || (desc.chan_size as usize) < size_of::<u32>() {
+            error!(
+                "Fastchannel at {:#x} of {} bytes cannot be accessed by words",
+                desc.chan_addr, desc.chan_size
+            );
+            return Err(ScmiError::InvalidParameters);
+        }
+        let doorbell = match (desc.doorbell, doorbell) {
+            (Some(db), Some(addr)) => {
+                if !(addr.as_ptr() as usize).is_multiple_of(db.width.bytes()) {
+                    error!("Fastchannel doorbell at {:#x} is misaligned", db.addr);
+                    return Err(ScmiError::InvalidParameters);
+                }
+                Some((addr, db))
+            }
+            (None, _) => None,
+            (Some(db), None) => {
+                error!("Fastchannel doorbell at {:#x} not mapped", db.addr);
+                return Err(ScmiError::InvalidParameters);
+            }
+        };
+        Ok(Fastchannel {
+            chan: chan.cast(),
+            words: desc.chan_size as usize / size_of::<u32>(),
+            rate_limit_us: desc.rate_limit_us,
+            doorbell,
+        })
+    }
+
+    /// Minimum interval between updates, in microseconds.
+    pub fn rate_limit_us(&self) -> u32 {
+        self.rate_limit_us
+    }
+
+    /// Reads word `idx` of the channel.
+    pub fn read(&self, idx: usize) -> Result<u32, ScmiError> {
+        if idx >= self.words {
+            return Err(ScmiError::OutOfRange);
+        }
+        let val = unsafe { self.chan.add(idx).read_volatile() };
+        rmb();
+        Ok(val)
+    }
+
+    /// Writes `vals` from the start of the channel and rings the doorbell.
+    pub fn write(&mut self, vals: &[u32]) -> Result<(), ScmiError> {
+        if vals.len() > self.words {
+            return Err(ScmiError::OutOfRange);
+        }
+        for (i, &val) in vals.iter().enumerate() {
+            unsafe { self.chan.add(i).write_volatile(val) };
+        }
+        wmb();
+        self.ring_doorbell();
+        Ok(())
+    }
+
+    fn ring_doorbell(&mut self) {
+        let Some((addr, db)) = self.doorbell else {
+            return;
+        };
+        macro_rules! ring {
+            ($t:ty) => {{
+                let reg = addr.cast::<$t>();
+                unsafe {
+                    let val = (reg.read_volatile() & db.preserve_mask as $t) | db.set_mask as $t;
+                    reg.write_volatile(val);
+                }
+            }};
+        }
+        match db.width {
+            DoorbellWidth::U8 => ring!(u8),
+            DoorbellWidth::U16 => ring!(u16),
+            DoorbellWidth::U32 => ring!(u32),
+            DoorbellWidth::U64 => ring!(u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{MockTransport, Scmi};
+
+    const POWERCAP: u8 = 0x18;
+    const DESCRIBE_FASTCHANNEL: u8 = 0xC;
+
+    fn describe_resp(attributes: u32) -> DescribeFastchannelResp {
+        DescribeFastchannelResp {
+            attributes,
+            rate_limit: 1000,
+            chan_addr: 0x8000_1000,
+            chan_size: 8,
+            db_addr: 0x8000_2000,
+            db_set_mask: 0x1,
+            db_preserve_mask: 0xF0,
+        }
+    }
+
+    fn desc_with(doorbell: Option<FastchannelDoorbell>, chan_size: u32) -> FastchannelDesc {
+        FastchannelDesc {
+            chan_addr: 0x8000_1000,
+            chan_size,
+            rate_limit_us: 0,
+            doorbell,
+        }
+    }
+
+    fn doorbell(width: DoorbellWidth) -> FastchannelDoorbell {
+        FastchannelDoorbell {
+            addr: 0x8000_2000,
+            width,
+            set_mask: 0x1,
+            preserve_mask: 0xF0,
+        }
+    }
+
+    #[test]
+    fn describe_decodes_doorbell() {
+        let mock = MockTransport::new();
+        let req = DescribeFastchannel {
+            domain_id: 1,
+            message_id: 4,
+        };
+        // Doorbell of 32 bits.
+        mock.expect(POWERCAP, DESCRIBE_FASTCHANNEL, req, describe_resp(0b101));
+        let scmi = Scmi::new(mock.clone()).unwrap();
+        let mut protocol = scmi.open_protocol(POWERCAP);
+
+        let desc = describe(&mut protocol, DESCRIBE_FASTCHANNEL, 1, 4).unwrap();
+        assert_eq!(
+            desc,
+            FastchannelDesc {
+                chan_addr: 0x8000_1000,
+                chan_size: 8,
+                rate_limit_us: 1000,
+                doorbell: Some(doorbell(DoorbellWidth::U32)),
+            }
+        );
+        assert_eq!(mock.pending(), 0);
+    }
+
+    #[test]
+    fn describe_without_doorbell() {
+        let mock = MockTransport::new();
+        let req = DescribeFastchannel {
+            domain_id: 0,
+            message_id: 5,
+        };
+        // Width bits are ignored without a doorbell.
+        mock.expect(POWERCAP, DESCRIBE_FASTCHANNEL, req, describe_resp(0b110));
+        let scmi = Scmi::new(mock.clone()).unwrap();
+        let mut protocol = scmi.open_protocol(POWERCAP);
+
+        let desc = describe(&mut protocol, DESCRIBE_FASTCHANNEL, 0, 5).unwrap();
+        assert_eq!(desc.doorbell, None);
+    }
+
+    #[test]
+    fn doorbell_keeps_preserved_bits() {
+        let mut chan = vec![0u32; 2];
+        let mut db = vec![0xFFFF_FFFFu32, 0xFFFF_FFFF];
+        let chan_ptr = NonNull::new(chan.as_mut_ptr()).unwrap().cast();
+        let db_ptr = NonNull::new(db.as_mut_ptr()).unwrap().cast();
+
+        let desc = desc_with(Some(doorbell(DoorbellWidth::U32)), 8);
+        let mut fc = unsafe { Fastchannel::new(&desc, chan_ptr, Some(db_ptr)) }.unwrap();
+        fc.write(&[7, 9]).unwrap();
+        assert_eq!(chan, [7, 9]);
+        assert_eq!(fc.read(1), Ok(9));
+        assert_eq!(fc.read(2), Err(ScmiError::OutOfRange));
+        assert_eq!(fc.write(&[1, 2, 3]), Err(ScmiError::OutOfRange));
+        assert_eq!(db, [0xF1, 0xFFFF_FFFF]);
+
+        // A byte wide doorbell leaves the rest of the word alone.
+        db[0] = 0x1234_5602;
+        let desc = desc_with(Some(doorbell(DoorbellWidth::U8)), 8);
+        let mut fc = unsafe { Fastchannel::new(&desc, chan_ptr, Some(db_ptr)) }.unwrap();
+        fc.write(&[0]).unwrap();
+        assert_eq!(db[0].to_le_bytes()[0], 0x01);
+        assert_eq!(db[0] & 0xFFFF_FF00, 0x1234_5600);
+    }
+
+    #[test]
+    fn new_rejects_bad_mappings() {
+        let mut mem = vec![0u64; 2];
+        let base = NonNull::new(mem.as_mut_ptr()).unwrap().cast::<u8>();
+        let odd = unsafe { base.add(2) };
+        let new =
+            |desc: &FastchannelDesc, chan, db| unsafe { Fastchannel::new(desc, chan, db) }.err();
+
+        let plain = desc_with(None, 8);
+        assert_eq!(new(&plain, odd, None), Some(ScmiError::InvalidParameters));
+        assert_eq!(
+            new(&desc_with(None, 2), base, None),
+            Some(ScmiError::InvalidParameters)
+        );
+
+        let wide = desc_with(Some(doorbell(DoorbellWidth::U64)), 8);
+        assert_eq!(new(&wide, base, None), Some(ScmiError::InvalidParameters));
+        assert_eq!(
+            new(&wide, base, Some(unsafe { base.add(4) })),
+            Some(ScmiError::InvalidParameters)
+        );
+        assert_eq!(new(&wide, base, Some(unsafe { base.add(8) })), None);
+    }
+}
@@ -1,18 +1,44 @@
-use core::sync::atomic::{AtomicI32, Ordering};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
 
-use alloc::vec::Vec;
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
 use mbarrier::smp_mb;
-use spin::Mutex;
 
 use crate::{Data, Transport, err::ScmiError};
 
 pub mod clock;
+pub mod codec;
+pub mod fastchannel;
+mod perf;
+mod powercap;
 
 pub use clock::Clock;
+use codec::{Decode, Encode};
+pub use perf::Perf;
+pub use powercap::Powercap;
 
 const PROTOCOL_VERSION: u8 = 0;
 const PROTOCOL_ATTRIBUTES: u8 = 0x1;
 
+/// A protocol that can be opened with [`Scmi::protocol`](crate::Scmi::protocol).
+///
+/// Implement it to add protocols, e.g. vendor ones in the `0x80..=0xFF`
+/// range, outside of this crate.
+pub trait ScmiProtocol<T: Transport>: Sized {
+    const PROTOCOL_ID: u8;
+    /// Oldest `(major, minor)` protocol version the implementation handles.
+    const MIN_VERSION: (u16, u16);
+    /// Newest `(major, minor)` protocol version the implementation knows
+    /// about. Newer platforms are still accepted, with a warning.
+    const MAX_VERSION: (u16, u16);
+
+    /// Discovery hook, called once the platform version has been checked.
+    fn init(protocol: Protocal<T>, version: (u16, u16)) -> Result<Self, ScmiError>;
+}
+
 pub struct Protocal<T: Transport> {
     data: Data<T>,
     id: u8,
@@ -23,6 +49,11 @@ impl<T: Transport> Protocal<T> {
         Self { data, id }
     }
 
+    /// Protocol identifier used for every message sent through this handle.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
     pub fn do_xfer<'a, R, F>(
         &'a mut self,
         mut xfer: Xfer,
@@ -32,6 +63,7 @@ impl<T: Transport> Protocal<T> {
         F: Fn(&mut Xfer) -> Result<R, ScmiError>,
     {
         xfer.hdr.protocol_id = self.id;
+        xfer.hdr.poll_completion = self.data.lock().transport.no_completion_irq();
 
         xfer.hdr.clear_status();
         xfer.status = XferStatus::Init;
@@ -44,14 +76,49 @@ impl<T: Transport> Protocal<T> {
         }
     }
 
-    pub fn version(&mut self) -> impl FuturePoll<Output = (u16, u16)> + '_ {
-        let xfer = Xfer::new(PROTOCOL_VERSION, 4);
-        self.do_xfer(xfer, |xfer| {
-            let version = u32::from_le_bytes([xfer.rx[0], xfer.rx[1], xfer.rx[2], xfer.rx[3]]);
+    /// Sends `msg_id` with `req` as payload and decodes the response as `R`.
+    ///
+    /// Responses may be longer than `R`, up to the transport's largest
+    /// message, for fields appended by newer protocol versions.
+    pub fn xfer<Q: Encode, R: Decode>(
+        &mut self,
+        msg_id: u8,
+        req: Q,
+    ) -> Result<impl XferPoll<R>, ScmiError> {
+        let mut xfer = Xfer::new(msg_id, self.max_rx(R::SIZE))?;
+        req.encode(&mut codec::Writer::new(&mut xfer.tx))?;
+        Ok(self.do_xfer(xfer, |xfer| codec::decode(&xfer.rx)))
+    }
+
+    /// Sends `msg_id` with an opaque payload and returns the raw response
+    /// payload, without the status word, at most `rx_len` bytes long.
+    pub fn raw_xfer(
+        &mut self,
+        msg_id: u8,
+        tx: &[u8],
+        rx_len: usize,
+    ) -> Result<impl XferPoll<Vec<u8>> + '_, ScmiError> {
+        let mut xfer = Xfer::new(msg_id, rx_len)?;
+        xfer.tx
+            .try_reserve(tx.len())
+            .map_err(|_| ScmiError::NoMemory)?;
+        xfer.tx.extend_from_slice(tx);
+        Ok(self.do_xfer(xfer, |xfer| Ok(xfer.rx.clone())))
+    }
+
+    /// Receive buffer for a response of at least `size` bytes.
+    fn max_rx(&self, size: usize) -> usize {
+        self.data.lock().transport.max_msg_size().max(size)
+    }
+
+    pub fn version(&mut self) -> Result<impl XferPoll<(u16, u16)> + '_, ScmiError> {
+        let xfer = Xfer::new(PROTOCOL_VERSION, self.max_rx(u32::SIZE))?;
+        Ok(self.do_xfer(xfer, |xfer| {
+            let version: u32 = codec::decode(&xfer.rx)?;
             let major = (version >> 16) as u16;
             let minor = (version & 0xFFFF) as u16;
             Ok((major, minor))
-        })
+        }))
     }
 }
 
@@ -60,39 +127,120 @@ pub trait FuturePoll {
     fn poll_completion(&mut self) -> nb::Result<Self::Output, ScmiError>;
 }
 
+/// A transfer that can be driven either with [`FuturePoll`] or by awaiting it.
+pub trait XferPoll<R>: FuturePoll<Output = R> + Future<Output = Result<R, ScmiError>> {}
+
+impl<X, R> XferPoll<R> for X where X: FuturePoll<Output = R> + Future<Output = Result<R, ScmiError>> {}
+
+/// An SCMI transfer in flight.
+///
+/// It can be driven to completion either with [`FuturePoll::poll_completion`]
+/// (e.g. through `nb::block!`) or by awaiting it. When awaited and the
+/// transport signals completion by interrupt, the task is only woken again
+/// from [`Scmi::handle_irq`](crate::Scmi::handle_irq).
 pub struct XferFuture<'a, T: Transport, R, F: Fn(&mut Xfer) -> Result<R, ScmiError>> {
     protocol: &'a mut Protocal<T>,
     xfer: Xfer,
     on_complete: F,
 }
 
-impl<'a, T: Transport, R, F: Fn(&mut Xfer) -> Result<R, ScmiError>> FuturePoll
-    for XferFuture<'a, T, R, F>
-{
-    type Output = R;
-
-    fn poll_completion(&mut self) -> nb::Result<R, ScmiError> {
-        trace!("Polling completion: xfer status={:?}", self.xfer.status);
+impl<'a, T: Transport, R, F: Fn(&mut Xfer) -> Result<R, ScmiError>> XferFuture<'a, T, R, F> {
+    fn step(&mut self) -> nb::Result<R, ScmiError> {
         match self.xfer.status {
             XferStatus::Init => {
-                self.protocol.data.lock().send_message(&mut self.xfer)?;
+                let mut data = self.protocol.data.lock();
+                data.xfers.alloc(&mut self.xfer)?;
+                data.send_message(&mut self.xfer)?;
                 self.xfer.status = XferStatus::SendOk;
                 Err(nb::Error::WouldBlock)
             }
             XferStatus::SendOk => {
-                self.protocol.data.lock().fetch_response(&mut self.xfer)?;
+                let mut data = self.protocol.data.lock();
+                if !data.response_ready(&self.xfer) {
+                    return Err(nb::Error::WouldBlock);
+                }
+                data.fetch_response(&mut self.xfer)?;
                 self.xfer.status = XferStatus::RespOk;
                 Err(nb::Error::WouldBlock)
             }
-            XferStatus::RespOk => {
-                let res = (self.on_complete)(&mut self.xfer)?;
-                self.protocol.data.lock().shmem.reset();
-                Ok(res)
+            XferStatus::RespOk => Ok((self.on_complete)(&mut self.xfer)?),
+        }
+    }
+
+    /// Hands the channel and the token back once the transfer is over,
+    /// whether it was answered, failed or given up on.
+    fn finish(&mut self) {
+        if !self.xfer.pending {
+            return;
+        }
+        let mut data = self.protocol.data.lock();
+        data.transport.mark_txdone(&self.xfer);
+        data.xfers.release(&mut self.xfer);
+    }
+}
+
+impl<'a, T: Transport, R, F: Fn(&mut Xfer) -> Result<R, ScmiError>> FuturePoll
+    for XferFuture<'a, T, R, F>
+{
+    type Output = R;
+
+    fn poll_completion(&mut self) -> nb::Result<R, ScmiError> {
+        trace!("Polling completion: xfer status={:?}", self.xfer.status);
+        let res = self.step();
+        if !matches!(res, Err(nb::Error::WouldBlock)) {
+            self.finish();
+        }
+        res
+    }
+}
+
+impl<'a, T: Transport, R, F: Fn(&mut Xfer) -> Result<R, ScmiError>> Future
+    for XferFuture<'a, T, R, F>
+{
+    type Output = Result<R, ScmiError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let status = this.xfer.status;
+            match this.poll_completion() {
+                Ok(res) => return Poll::Ready(Ok(res)),
+                Err(nb::Error::Other(e)) => return Poll::Ready(Err(e)),
+                Err(nb::Error::WouldBlock) if this.xfer.status != status => continue,
+                Err(nb::Error::WouldBlock) => {}
+            }
+
+            if this.xfer.hdr.poll_completion {
+                // No completion interrupt, keep the executor polling us.
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            // Register before checking again so that an interrupt firing in
+            // between is not lost.
+            let mut data = this.protocol.data.lock();
+            data.xfers.set_waker(this.xfer.hdr.seq, cx.waker());
+            if !data.response_ready(&this.xfer) {
+                return Poll::Pending;
             }
         }
     }
 }
 
+// The future never hands out pinned references to its fields.
+impl<'a, T: Transport, R, F: Fn(&mut Xfer) -> Result<R, ScmiError>> Unpin
+    for XferFuture<'a, T, R, F>
+{
+}
+
+impl<'a, T: Transport, R, F: Fn(&mut Xfer) -> Result<R, ScmiError>> Drop
+    for XferFuture<'a, T, R, F>
+{
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
 #[allow(dead_code)]
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -108,9 +256,6 @@ pub enum ScmiStdProtocol {
     Powercap = 0x18,
 }
 
-static TRANSFER_ID_COUNTER: AtomicI32 = AtomicI32::new(0);
-static TOKEN_ALLOCATOR: Mutex<TokenTable> = Mutex::new(TokenTable::new());
-
 const fn genmask(high: u32, low: u32) -> u32 {
     if high >= 32 || low >= 32 || high < low {
         0
@@ -136,6 +281,11 @@ const MSG_PROTOCOL_ID_MASK: u32 = genmask(17, 10);
 const MSG_TOKEN_ID_MASK: u32 = genmask(27, 18);
 const MSG_TOKEN_MAX: usize = mask_to_max(MSG_TOKEN_ID_MASK) as usize + 1;
 
+#[inline(always)]
+fn field_get(mask: u32, value: u32) -> u32 {
+    (value & mask) >> mask.trailing_zeros()
+}
+
 #[inline(always)]
 fn field_prep(mask: u32, value: u32) -> u32 {
     let shift = mask.trailing_zeros();
@@ -172,6 +322,29 @@ impl MsgHeader {
             | field_prep(MSG_PROTOCOL_ID_MASK, self.protocol_id.into())
     }
 
+    /// Decodes a raw message header received from the platform.
+    pub fn unpack(raw: u32) -> Self {
+        Self {
+            id: field_get(MSG_ID_MASK, raw) as u8,
+            protocol_id: field_get(MSG_PROTOCOL_ID_MASK, raw) as u8,
+            type_: MsgType::from_raw(field_get(MSG_TYPE_MASK, raw)),
+            seq: Self::token_of(raw),
+            ..Default::default()
+        }
+    }
+
+    /// Extracts the token from a raw message header.
+    pub fn token_of(raw: u32) -> u16 {
+        field_get(MSG_TOKEN_ID_MASK, raw) as u16
+    }
+
+    /// Checks that a header read back from the platform belongs to this
+    /// message: the token, protocol id and message id must all match.
+    pub fn matches(&self, raw: u32) -> bool {
+        const MASK: u32 = MSG_ID_MASK | MSG_PROTOCOL_ID_MASK | MSG_TOKEN_ID_MASK;
+        (raw ^ self.pack()) & MASK == 0
+    }
+
     pub fn to_result(&self) -> Result<(), ScmiError> {
         ScmiError::from_status(self.status as i32)
     }
@@ -190,6 +363,24 @@ pub enum MsgType {
     Notification = 3,
 }
 
+impl MsgType {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            2 => MsgType::DelayedResponse,
+            3 => MsgType::Notification,
+            _ => MsgType::Command,
+        }
+    }
+}
+
+/// A message initiated by the platform: a notification or the delayed
+/// response to an asynchronous command.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub hdr: MsgHeader,
+    pub payload: Vec<u8>,
+}
+
 #[allow(dead_code)]
 type Refcount = i32;
 #[allow(dead_code)]
@@ -205,30 +396,27 @@ pub struct Xfer {
 }
 
 impl Xfer {
-    pub fn new(msg_id: u8, rx_size: usize) -> Self {
-        let transfer_id = TRANSFER_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
-        let token = TOKEN_ALLOCATOR
-            .lock()
-            .alloc(transfer_id)
-            .expect("Alloc token fail");
-
+    pub fn new(msg_id: u8, rx_size: usize) -> Result<Self, ScmiError> {
         let hdr = MsgHeader {
             id: msg_id,
-            seq: token,
             ..Default::default()
         };
 
-        let tx = Vec::with_capacity(32);
-        let rx = vec![0u8; rx_size];
+        let mut tx = Vec::new();
+        tx.try_reserve(32).map_err(|_| ScmiError::NoMemory)?;
+        let mut rx = Vec::new();
+        rx.try_reserve_exact(rx_size)
+            .map_err(|_| ScmiError::NoMemory)?;
+        rx.resize(rx_size, 0);
 
-        Self {
-            transfer_id,
+        Ok(Self {
+            transfer_id: 0,
             hdr,
             tx,
             rx,
             pending: false,
             status: XferStatus::SendOk,
-        }
+        })
     }
 
     pub fn token(&self) -> u16 {
@@ -236,12 +424,6 @@ impl Xfer {
     }
 }
 
-impl Drop for Xfer {
-    fn drop(&mut self) {
-        TOKEN_ALLOCATOR.lock().release(self.hdr.seq);
-    }
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum XferStatus {
     #[default]
@@ -250,48 +432,106 @@ pub enum XferStatus {
     RespOk = 2,
 }
 
-const TOKENS_PER_WORD: usize = 32;
-#[allow(clippy::manual_div_ceil)]
-const TOKEN_TABLE_WORDS: usize = (MSG_TOKEN_MAX + TOKENS_PER_WORD - 1) / TOKENS_PER_WORD;
-
-const fn token_table_init() -> [u32; TOKEN_TABLE_WORDS] {
-    [0; TOKEN_TABLE_WORDS]
+/// A transfer that has been handed to the platform and not answered yet.
+#[derive(Debug, Clone)]
+pub(crate) struct PendingXfer {
+    pub transfer_id: i32,
+    pub hdr: MsgHeader,
+    pub waker: Option<Waker>,
 }
 
-struct TokenTable {
-    bitmap: [u32; TOKEN_TABLE_WORDS],
+/// Per-instance table of in-flight transfers, keyed by token.
+///
+/// At most `max_msg` transfers may be outstanding at once. Tokens are handed
+/// out round-robin from a monotonically increasing transfer id, so a token is
+/// not reused right after it was released and a late reply to an abandoned
+/// transfer can not be mistaken for the answer to the next one.
+pub(crate) struct XferTable {
+    max_msg: usize,
+    next_id: i32,
+    pending: BTreeMap<u16, PendingXfer>,
 }
 
-impl TokenTable {
-    const fn new() -> Self {
-        TokenTable {
-            bitmap: token_table_init(),
+impl XferTable {
+    pub fn new(max_msg: usize) -> Self {
+        Self {
+            max_msg: max_msg.min(MSG_TOKEN_MAX),
+            next_id: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Reserves a token for `xfer` and records it as pending.
+    pub fn alloc(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        if self.pending.len() >= self.max_msg {
+            return Err(ScmiError::Busy);
         }
+        let transfer_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let base = transfer_id as u32 as usize % MSG_TOKEN_MAX;
+        let token = (0..MSG_TOKEN_MAX)
+            .map(|i| ((base + i) % MSG_TOKEN_MAX) as u16)
+            .find(|token| !self.pending.contains_key(token))
+            .ok_or(ScmiError::Busy)?;
+
+        xfer.transfer_id = transfer_id;
+        xfer.hdr.seq = token;
+        xfer.pending = true;
+        self.pending.insert(
+            token,
+            PendingXfer {
+                transfer_id,
+                hdr: xfer.hdr.clone(),
+                waker: None,
+            },
+        );
+        Ok(())
     }
 
-    fn alloc(&mut self, base: i32) -> Option<u16> {
-        let base = base as u16;
-        if self.is_token_not_used(base) {
-            return Some(base);
+    /// Remembers the task waiting on `token`.
+    pub fn set_waker(&mut self, token: u16, waker: &Waker) {
+        if let Some(p) = self.pending.get_mut(&token) {
+            match &mut p.waker {
+                Some(w) => w.clone_from(waker),
+                None => p.waker = Some(waker.clone()),
+            }
         }
-        (0..MSG_TOKEN_MAX as u16).find(|&token| self.is_token_not_used(token))
     }
 
-    fn is_token_not_used(&self, token: u16) -> bool {
-        let word_idx = token / TOKENS_PER_WORD as u16;
-        let bit_idx = token % TOKENS_PER_WORD as u16;
-        let mask = 1u32 << bit_idx;
-        (self.bitmap[word_idx as usize] & mask) == 0
+    /// Takes the waker of the task waiting on `token`, or those of every
+    /// waiting task when the token is not given or not known.
+    ///
+    /// The caller wakes them once the instance lock is released, as an
+    /// executor may poll the woken task right away.
+    pub fn take_wakers(&mut self, token: Option<u16>) -> Vec<Waker> {
+        if let Some(token) = token {
+            match self.pending.get_mut(&token) {
+                Some(p) => return p.waker.take().into_iter().collect(),
+                None => debug!("Completion for unknown token {token}, waking all"),
+            }
+        }
+        self.pending
+            .values_mut()
+            .filter_map(|p| p.waker.take())
+            .collect()
     }
 
-    fn release(&mut self, token: u16) {
-        let token = token as usize;
-        if token >= MSG_TOKEN_MAX {
+    /// Gives the token of `xfer` back to the table.
+    pub fn release(&mut self, xfer: &mut Xfer) {
+        if !xfer.pending {
             return;
         }
-        let word_idx = token / TOKENS_PER_WORD;
-        let bit_idx = token % TOKENS_PER_WORD;
-        let mask = !(1u32 << bit_idx);
-        self.bitmap[word_idx] &= mask;
+        xfer.pending = false;
+        match self.pending.get(&xfer.hdr.seq) {
+            Some(p) if p.transfer_id == xfer.transfer_id => {
+                self.pending.remove(&xfer.hdr.seq);
+            }
+            Some(p) => warn!(
+                "Token {} is owned by another transfer {:?}",
+                xfer.hdr.seq, p.hdr
+            ),
+            None => warn!("Releasing unknown transfer {:?}", xfer.hdr),
+        }
     }
 }
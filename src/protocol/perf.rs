@@ -0,0 +1,185 @@
+use nb::block;
+
+use crate::{
+    Transport,
+    err::ScmiError,
+    protocol::{
+        FuturePoll, Protocal, ScmiProtocol, XferPoll,
+        fastchannel::{self, FastchannelDesc},
+    },
+};
+
+const PERF_DOMAIN_ATTRIBUTES: u8 = 0x3;
+const PERF_DESCRIBE_FASTCHANNEL: u8 = 0xB;
+
+const DOMAIN_FASTCHANNELS: u32 = 1 << 27;
+const NUM_DOMAINS_MASK: u32 = 0xFFFF;
+
+crate::scmi_message! {
+    struct PerfAttributes {
+        attributes: u32,
+        stats_addr: u64,
+        stats_size: u32,
+    }
+
+    struct PerfDomainAttributes {
+        attributes: u32,
+        rate_limit: u32,
+        sustained_freq_khz: u32,
+        sustained_perf_level: u32,
+        name: [u8; 16],
+    }
+
+    struct LimitsSet {
+        domain_id: u32,
+        max_level: u32,
+        min_level: u32,
+    }
+
+    struct Limits {
+        max_level: u32,
+        min_level: u32,
+    }
+
+    struct LevelSet {
+        domain_id: u32,
+        level: u32,
+    }
+}
+
+/// Performance domain management protocol.
+pub struct Perf<T: Transport> {
+    protocol: Protocal<T>,
+    version: (u16, u16),
+    num_domains: u16,
+}
+
+impl<T: Transport> ScmiProtocol<T> for Perf<T> {
+    const PROTOCOL_ID: u8 = 0x13;
+    const MIN_VERSION: (u16, u16) = (1, 0);
+    const MAX_VERSION: (u16, u16) = (4, 0);
+
+    fn init(protocol: Protocal<T>, version: (u16, u16)) -> Result<Self, ScmiError> {
+        debug!("Perf Protocol version: {}.{}", version.0, version.1);
+        let mut perf = Self {
+            protocol,
+            version,
+            num_domains: 0,
+        };
+        let res = {
+            let mut xfer = perf
+                .protocol
+                .xfer::<(), PerfAttributes>(super::PROTOCOL_ATTRIBUTES, ())?;
+            block!(xfer.poll_completion())
+        }?;
+        perf.num_domains = (res.attributes & NUM_DOMAINS_MASK) as u16;
+        debug!("Perf Protocol Attributes: num_domains={}", perf.num_domains);
+        Ok(perf)
+    }
+}
+
+impl<T: Transport> Perf<T> {
+    pub const LIMITS_SET: u8 = 0x5;
+    pub const LIMITS_GET: u8 = 0x6;
+    pub const LEVEL_SET: u8 = 0x7;
+    pub const LEVEL_GET: u8 = 0x8;
+
+    /// Protocol version reported by the platform.
+    pub fn version(&self) -> (u16, u16) {
+        self.version
+    }
+
+    pub fn num_domains(&self) -> u16 {
+        self.num_domains
+    }
+
+    pub fn level_get(&mut self, domain_id: u32) -> Result<u32, ScmiError> {
+        let mut xfer = self.xfer_level_get(domain_id)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn level_get_async(&mut self, domain_id: u32) -> Result<u32, ScmiError> {
+        self.xfer_level_get(domain_id)?.await
+    }
+
+    pub fn level_set(&mut self, domain_id: u32, level: u32) -> Result<(), ScmiError> {
+        let mut xfer = self.xfer_level_set(domain_id, level)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn level_set_async(&mut self, domain_id: u32, level: u32) -> Result<(), ScmiError> {
+        self.xfer_level_set(domain_id, level)?.await
+    }
+
+    /// Returns the `(max, min)` performance levels allowed for the domain.
+    pub fn limits_get(&mut self, domain_id: u32) -> Result<(u32, u32), ScmiError> {
+        let res = {
+            let mut xfer = self
+                .protocol
+                .xfer::<_, Limits>(Self::LIMITS_GET, domain_id)?;
+            block!(xfer.poll_completion())
+        }?;
+        Ok((res.max_level, res.min_level))
+    }
+
+    pub fn limits_set(
+        &mut self,
+        domain_id: u32,
+        max_level: u32,
+        min_level: u32,
+    ) -> Result<(), ScmiError> {
+        let req = LimitsSet {
+            domain_id,
+            max_level,
+            min_level,
+        };
+        let mut xfer = self.protocol.xfer::<_, ()>(Self::LIMITS_SET, req)?;
+        block!(xfer.poll_completion())
+    }
+
+    /// Describes the fastchannel of `message_id`, one of
+    /// [`Perf::LEVEL_SET`], [`Perf::LEVEL_GET`], [`Perf::LIMITS_SET`] and
+    /// [`Perf::LIMITS_GET`], for the domain.
+    ///
+    /// Levels are one word, limits are the max then the min level.
+    pub fn describe_fastchannel(
+        &mut self,
+        domain_id: u32,
+        message_id: u8,
+    ) -> Result<FastchannelDesc, ScmiError> {
+        if !matches!(
+            message_id,
+            Self::LIMITS_SET | Self::LIMITS_GET | Self::LEVEL_SET | Self::LEVEL_GET
+        ) {
+            return Err(ScmiError::InvalidParameters);
+        }
+        let attrs = {
+            let mut xfer = self
+                .protocol
+                .xfer::<_, PerfDomainAttributes>(PERF_DOMAIN_ATTRIBUTES, domain_id)?;
+            block!(xfer.poll_completion())
+        }?;
+        if attrs.attributes & DOMAIN_FASTCHANNELS == 0 {
+            return Err(ScmiError::NotSupported);
+        }
+        fastchannel::describe(
+            &mut self.protocol,
+            PERF_DESCRIBE_FASTCHANNEL,
+            domain_id,
+            message_id,
+        )
+    }
+
+    fn xfer_level_get(&mut self, domain_id: u32) -> Result<impl XferPoll<u32> + '_, ScmiError> {
+        self.protocol.xfer(Self::LEVEL_GET, domain_id)
+    }
+
+    fn xfer_level_set(
+        &mut self,
+        domain_id: u32,
+        level: u32,
+    ) -> Result<impl XferPoll<()> + '_, ScmiError> {
+        self.protocol
+            .xfer(Self::LEVEL_SET, LevelSet { domain_id, level })
+    }
+}
@@ -0,0 +1,173 @@
+use nb::block;
+
+use crate::{
+    Transport,
+    err::ScmiError,
+    protocol::{
+        FuturePoll, Protocal, ScmiProtocol, XferPoll,
+        fastchannel::{self, FastchannelDesc},
+    },
+};
+
+const POWERCAP_DOMAIN_ATTRIBUTES: u8 = 0x3;
+const POWERCAP_DESCRIBE_FASTCHANNEL: u8 = 0xC;
+
+const DOMAIN_FASTCHANNELS: u32 = 1 << 22;
+const NUM_DOMAINS_MASK: u32 = 0xFFFF;
+
+crate::scmi_message! {
+    struct PowercapDomainAttributes {
+        attributes: u32,
+        name: [u8; 16],
+        min_pai: u32,
+        max_pai: u32,
+        pai_step: u32,
+        min_power_cap: u32,
+        max_power_cap: u32,
+        power_cap_step: u32,
+        sustainable_power: u32,
+        accuracy: u32,
+        parent_id: u32,
+    }
+
+    /// Shared by `POWERCAP_CAP_SET` and `POWERCAP_PAI_SET`.
+    struct ValueSet {
+        domain_id: u32,
+        flags: u32,
+        value: u32,
+    }
+}
+
+/// Power capping and monitoring protocol.
+pub struct Powercap<T: Transport> {
+    protocol: Protocal<T>,
+    version: (u16, u16),
+    num_domains: u16,
+}
+
+impl<T: Transport> ScmiProtocol<T> for Powercap<T> {
+    const PROTOCOL_ID: u8 = 0x18;
+    const MIN_VERSION: (u16, u16) = (1, 0);
+    const MAX_VERSION: (u16, u16) = (2, 0);
+
+    fn init(protocol: Protocal<T>, version: (u16, u16)) -> Result<Self, ScmiError> {
+        debug!("Powercap Protocol version: {}.{}", version.0, version.1);
+        let mut powercap = Self {
+            protocol,
+            version,
+            num_domains: 0,
+        };
+        let attributes = {
+            let mut xfer = powercap
+                .protocol
+                .xfer::<(), u32>(super::PROTOCOL_ATTRIBUTES, ())?;
+            block!(xfer.poll_completion())
+        }?;
+        powercap.num_domains = (attributes & NUM_DOMAINS_MASK) as u16;
+        debug!(
+            "Powercap Protocol Attributes: num_domains={}",
+            powercap.num_domains
+        );
+        Ok(powercap)
+    }
+}
+
+impl<T: Transport> Powercap<T> {
+    pub const CAP_GET: u8 = 0x4;
+    pub const CAP_SET: u8 = 0x5;
+    pub const PAI_GET: u8 = 0x6;
+    pub const PAI_SET: u8 = 0x7;
+
+    /// Protocol version reported by the platform.
+    pub fn version(&self) -> (u16, u16) {
+        self.version
+    }
+
+    pub fn num_domains(&self) -> u16 {
+        self.num_domains
+    }
+
+    /// Power cap of the domain, in the platform's power unit.
+    pub fn cap_get(&mut self, domain_id: u32) -> Result<u32, ScmiError> {
+        let mut xfer = self.xfer_get(Self::CAP_GET, domain_id)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn cap_get_async(&mut self, domain_id: u32) -> Result<u32, ScmiError> {
+        self.xfer_get(Self::CAP_GET, domain_id)?.await
+    }
+
+    pub fn cap_set(&mut self, domain_id: u32, cap: u32) -> Result<(), ScmiError> {
+        let mut xfer = self.xfer_set(Self::CAP_SET, domain_id, cap)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub async fn cap_set_async(&mut self, domain_id: u32, cap: u32) -> Result<(), ScmiError> {
+        self.xfer_set(Self::CAP_SET, domain_id, cap)?.await
+    }
+
+    /// Power averaging interval of the domain, in microseconds.
+    pub fn pai_get(&mut self, domain_id: u32) -> Result<u32, ScmiError> {
+        let mut xfer = self.xfer_get(Self::PAI_GET, domain_id)?;
+        block!(xfer.poll_completion())
+    }
+
+    pub fn pai_set(&mut self, domain_id: u32, pai: u32) -> Result<(), ScmiError> {
+        let mut xfer = self.xfer_set(Self::PAI_SET, domain_id, pai)?;
+        block!(xfer.poll_completion())
+    }
+
+    /// Describes the fastchannel of `message_id`, one of
+    /// [`Powercap::CAP_SET`], [`Powercap::CAP_GET`], [`Powercap::PAI_SET`] and
+    /// [`Powercap::PAI_GET`], for the domain.
+    pub fn describe_fastchannel(
+        &mut self,
+        domain_id: u32,
+        message_id: u8,
+    ) -> Result<FastchannelDesc, ScmiError> {
+        if !matches!(
+            message_id,
+            Self::CAP_SET | Self::CAP_GET | Self::PAI_SET | Self::PAI_GET
+        ) {
+            return Err(ScmiError::InvalidParameters);
+        }
+        let attrs = {
+            let mut xfer = self
+                .protocol
+                .xfer::<_, PowercapDomainAttributes>(POWERCAP_DOMAIN_ATTRIBUTES, domain_id)?;
+            block!(xfer.poll_completion())
+        }?;
+        if attrs.attributes & DOMAIN_FASTCHANNELS == 0 {
+            return Err(ScmiError::NotSupported);
+        }
+        fastchannel::describe(
+            &mut self.protocol,
+            POWERCAP_DESCRIBE_FASTCHANNEL,
+            domain_id,
+            message_id,
+        )
+    }
+
+    fn xfer_get(
+        &mut self,
+        msg_id: u8,
+        domain_id: u32,
+    ) -> Result<impl XferPoll<u32> + '_, ScmiError> {
+        self.protocol.xfer(msg_id, domain_id)
+    }
+
+    fn xfer_set(
+        &mut self,
+        msg_id: u8,
+        domain_id: u32,
+        value: u32,
+    ) -> Result<impl XferPoll<()> + '_, ScmiError> {
+        // Synchronous, without delayed response.
+        let req = ValueSet {
+            domain_id,
+            flags: 0,
+            value,
+        };
+        self.protocol.xfer(msg_id, req)
+    }
+}
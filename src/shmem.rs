@@ -1,9 +1,18 @@
-use core::ptr::NonNull;
+use core::{ops::Range, ptr::NonNull};
 
 use mbarrier::{rmb, wmb};
 use tock_registers::{interfaces::*, registers::*};
 
-use crate::Xfer;
+#[cfg(target_arch = "aarch64")]
+use aarch64_cpu_ext::cache::{self, CacheOp};
+use alloc::vec::Vec;
+use dma_api::{DVec, Direction};
+
+use crate::{
+    Xfer,
+    err::ScmiError,
+    protocol::{MsgHeader, Notification},
+};
 
 tock_registers::register_structs! {
     pub ShmemHeader {
@@ -20,45 +29,247 @@ tock_registers::register_structs! {
 tock_registers::register_bitfields![
     u32,
     ChannelStatus [
-        STATUS OFFSET(0) NUMBITS(2) [
-            FREE = 0,
-            ERROR = 0b10,
-        ]
+        FREE OFFSET(0) NUMBITS(1) [],
+        ERROR OFFSET(1) NUMBITS(1) [],
     ],
     ShmemFlags [
         INTR_ENABLED OFFSET(0) NUMBITS(1) [],
     ],
 ];
 
+/// How messages are framed in a channel's memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShmemLayout {
+    /// Shared Memory Transport: the message follows the channel status,
+    /// flags and length words of [`ShmemHeader`].
+    #[default]
+    Smt,
+    /// Bare message header and payload. There is no channel status and the
+    /// transport reports the length of received messages.
+    Msg,
+}
+
+/// Width of the accesses copying payloads to and from a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShmemAccess {
+    /// Aligned 32-bit accesses, bytes for the unaligned head and tail of a
+    /// copy.
+    #[default]
+    Auto,
+    /// Byte accesses only.
+    U8,
+    /// 32-bit accesses only, for interconnects rejecting narrower ones.
+    /// Partial words are read, merged and written back.
+    U32,
+}
+
+/// Memory of one channel.
+///
+/// It is either mapped by the caller ([`Shmem::new`]), as for platform
+/// provided channels described by an `arm,scmi-shmem` node, or allocated
+/// from dma-api ([`Shmem::alloc`]) for transports where the agent provides
+/// the memory, such as virtio, FF-A or OP-TEE dynamic shared memory.
 pub struct Shmem {
-    pub address: NonNull<u8>,
-    pub bus_address: usize,
-    pub size: usize,
+    address: NonNull<u8>,
+    bus_address: usize,
+    size: usize,
+    layout: ShmemLayout,
+    access: ShmemAccess,
+    cache_maintenance: bool,
+    /// Allocation backing the channel, freed on drop.
+    dma: Option<DVec<u8>>,
 }
 
 impl Shmem {
+    /// Alignment of allocated channels, a page so that the platform can map
+    /// them on their own.
+    pub const ALLOC_ALIGN: usize = 0x1000;
+
+    /// Largest payload exchanged by the transports of this crate. A channel
+    /// must hold it after its header and the status word.
+    pub const MAX_MSG_SIZE: usize = 128;
+
+    /// Wraps channel memory mapped by the caller.
+    ///
+    /// Fails with [`ScmiError::InvalidParameters`] if `address` is not word
+    /// aligned, and with [`ScmiError::InvalidLength`] if `size` cannot hold
+    /// the header of `layout` and a [`Shmem::MAX_MSG_SIZE`] message.
+    ///
+    /// # Safety
+    ///
+    /// `address` must be valid for volatile reads and writes of `size` bytes
+    /// for as long as the channel is used, and `bus_address` must be where
+    /// the platform sees it.
+    pub unsafe fn new(
+        address: NonNull<u8>,
+        bus_address: usize,
+        size: usize,
+        layout: ShmemLayout,
+    ) -> Result<Self, ScmiError> {
+        if !address.cast::<u32>().is_aligned() {
+            error!("SHMEM at {address:p} is not word aligned");
+            return Err(ScmiError::InvalidParameters);
+        }
+        Self::check_size(size, layout)?;
+        Ok(Shmem {
+            address,
+            bus_address,
+            size,
+            layout,
+            access: ShmemAccess::default(),
+            cache_maintenance: false,
+            dma: None,
+        })
+    }
+
+    /// Allocates a zeroed channel of `size` bytes through dma-api.
+    ///
+    /// dma-api must have been initialized with an `Osal` handing out memory
+    /// coherent with the platform. The bus address is the one returned by its
+    /// mapping.
+    pub fn alloc(size: usize, layout: ShmemLayout) -> Result<Self, ScmiError> {
+        Self::alloc_with_mask(size, layout, u64::MAX)
+    }
+
+    /// Same as [`Shmem::alloc`] for a platform that can only reach bus
+    /// addresses within `dma_mask`.
+    pub fn alloc_with_mask(
+        size: usize,
+        layout: ShmemLayout,
+        dma_mask: u64,
+    ) -> Result<Self, ScmiError> {
+        Self::check_size(size, layout)?;
+        let dma = DVec::zeros(dma_mask, size, Self::ALLOC_ALIGN, Direction::Bidirectional)
+            .map_err(|e| {
+                error!("Failed to allocate {size:#x} bytes of shared memory: {e}");
+                ScmiError::NoMemory
+            })?;
+        let address = NonNull::new(dma.as_ptr()).ok_or(ScmiError::NoMemory)?;
+        trace!(
+            "Allocated SHMEM at {address:p}, bus address {:#x}",
+            dma.bus_addr()
+        );
+        Ok(Shmem {
+            address,
+            bus_address: dma.bus_addr() as usize,
+            size,
+            layout,
+            access: ShmemAccess::default(),
+            cache_maintenance: false,
+            dma: Some(dma),
+        })
+    }
+
+    fn check_size(size: usize, layout: ShmemLayout) -> Result<(), ScmiError> {
+        let min = Self::payload_offset_of(layout) + size_of::<u32>() + Self::MAX_MSG_SIZE;
+        if size < min {
+            error!("SHMEM of {size:#x} bytes too small, {layout:?} channels need {min:#x}");
+            return Err(ScmiError::InvalidLength(size));
+        }
+        Ok(())
+    }
+
+    /// Whether the channel memory is owned and freed with it.
+    pub fn is_allocated(&self) -> bool {
+        self.dma.is_some()
+    }
+
+    /// CPU address of the channel.
+    pub fn address(&self) -> NonNull<u8> {
+        self.address
+    }
+
+    /// Address of the channel as seen by the platform.
+    pub fn bus_address(&self) -> usize {
+        self.bus_address
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn layout(&self) -> ShmemLayout {
+        self.layout
+    }
+
+    pub fn access(&self) -> ShmemAccess {
+        self.access
+    }
+
+    /// Sets the width of payload accesses, [`ShmemAccess::Auto`] by default.
+    pub fn set_access(&mut self, access: ShmemAccess) {
+        self.access = access;
+    }
+
+    pub fn cache_maintenance(&self) -> bool {
+        self.cache_maintenance
+    }
+
+    /// Cleans the data cache over what the agent writes to the channel, and
+    /// invalidates it over what it reads from the platform.
+    ///
+    /// Needed when the channel is mapped as cacheable normal memory, or the
+    /// platform side is not coherent with the agent caches. The channel should
+    /// then be cache line aligned so that maintenance does not reach
+    /// neighbouring data. Off by default, it does nothing off aarch64.
+    pub fn set_cache_maintenance(&mut self, enable: bool) {
+        self.cache_maintenance = enable;
+    }
+
     pub fn reset(&mut self) {
         trace!("Reset SHMEM at {:p}", self.address);
-        self.header().channel_status.set(0);
-        self.header().flags.set(0);
-        self.header().length.set(0);
-        self.header().msg_header.set(0);
+        match self.layout {
+            ShmemLayout::Smt => {
+                self.header().channel_status.set(0);
+                self.header().flags.set(0);
+                self.header().length.set(0);
+                self.header().msg_header.set(0);
+            }
+            ShmemLayout::Msg => self.write_msg_header(0),
+        }
+        self.clean_dcache(0, self.payload_offset());
+    }
+
+    /// A MSG layout channel over `size` bytes at `offset` of this area.
+    ///
+    /// The view does not own the memory and must not outlive this channel.
+    pub(crate) fn msg_slice(&self, offset: usize, size: usize) -> Shmem {
+        debug_assert!(offset + size <= self.size);
+        debug_assert!(Self::check_size(size, ShmemLayout::Msg).is_ok());
+        Shmem {
+            address: unsafe { self.address.add(offset) },
+            bus_address: self.bus_address + offset,
+            size,
+            layout: ShmemLayout::Msg,
+            access: self.access,
+            cache_maintenance: self.cache_maintenance,
+            dma: None,
+        }
     }
 
     pub(crate) fn header(&mut self) -> &mut ShmemHeader {
         unsafe { &mut *(self.address.as_ptr() as *mut ShmemHeader) }
     }
-    pub fn tx_prepare(&mut self, xfer: &Xfer) {
-        self.header().channel_status.set(0);
-        // self.header().flags.set(0);
-        if xfer.hdr.poll_completion {
-            self.header().flags.modify(ShmemFlags::INTR_ENABLED::CLEAR);
-        } else {
-            self.header().flags.modify(ShmemFlags::INTR_ENABLED::SET);
+    /// Writes `xfer` as a command and returns the message length, header
+    /// included.
+    ///
+    /// The channel is left untouched if the payload does not fit in it.
+    pub fn tx_prepare(&mut self, xfer: &Xfer) -> Result<usize, ScmiError> {
+        self.check_payload(0, xfer.tx.len())?;
+        let len = size_of::<u32>() + xfer.tx.len();
+        match self.layout {
+            ShmemLayout::Smt => {
+                self.header().channel_status.set(0);
+                if xfer.hdr.poll_completion {
+                    self.header().flags.modify(ShmemFlags::INTR_ENABLED::CLEAR);
+                } else {
+                    self.header().flags.modify(ShmemFlags::INTR_ENABLED::SET);
+                }
+                self.header().length.set(len as u32);
+            }
+            ShmemLayout::Msg => {}
         }
-        let len = size_of::<u32>() as u32 + xfer.tx.len() as u32;
-        self.header().length.set(len);
-        self.header().msg_header.set(xfer.hdr.pack());
+        self.write_msg_header(xfer.hdr.pack());
 
         trace!(
             "Preparing TX: hdr={:?}, tx_len={}, all_len={len}",
@@ -67,35 +278,458 @@ impl Shmem {
         );
         /* Copy TX payload */
         if !xfer.tx.is_empty() {
-            self.write_payload(&xfer.tx);
+            self.write_payload(&xfer.tx)?;
+        }
+        self.clean_dcache(0, self.payload_offset());
+        Ok(len)
+    }
+
+    /// Reads the platform answer to `xfer` into `xfer.rx` from an SMT
+    /// channel.
+    ///
+    /// The response header must match the transfer, and the payload must fit
+    /// in the channel, in `max_msg_size` and in the expected `xfer.rx`.
+    pub fn fetch_response(
+        &mut self,
+        xfer: &mut Xfer,
+        max_msg_size: usize,
+    ) -> Result<(), ScmiError> {
+        // `length` covers the message header, the status word and the payload.
+        self.invalidate_dcache(0, self.payload_offset());
+        let len = self.header().length.get() as usize;
+        self.read_response(xfer, len, max_msg_size)
+    }
+
+    /// Same as [`Shmem::fetch_response`] for a MSG channel, where `len` is the
+    /// message length reported by the transport.
+    pub fn fetch_msg_response(
+        &mut self,
+        xfer: &mut Xfer,
+        len: usize,
+        max_msg_size: usize,
+    ) -> Result<(), ScmiError> {
+        self.read_response(xfer, len, max_msg_size)
+    }
+
+    fn read_response(
+        &mut self,
+        xfer: &mut Xfer,
+        len: usize,
+        max_msg_size: usize,
+    ) -> Result<(), ScmiError> {
+        // Header and status word.
+        self.invalidate_dcache(0, self.payload_offset() + WORD);
+        let msg_header = self.msg_header();
+        if !xfer.hdr.matches(msg_header) {
+            warn!(
+                "Dropping mismatched response {msg_header:#x}, expected {:#x} for {:?}",
+                xfer.hdr.pack(),
+                xfer.hdr
+            );
+            return Err(ScmiError::ProtocolError);
+        }
+
+        let max_rx = xfer
+            .rx
+            .len()
+            .min(max_msg_size)
+            .min(self.max_payload().saturating_sub(size_of::<u32>()));
+        let rx_len = match len.checked_sub(2 * size_of::<u32>()) {
+            Some(rx_len) if rx_len <= max_rx => rx_len,
+            _ => {
+                warn!(
+                    "Invalid response length {len} for {:?}, max payload {max_rx}",
+                    xfer.hdr
+                );
+                return Err(ScmiError::InvalidLength(len));
+            }
+        };
+
+        xfer.hdr.status = unsafe { (self.payload_ptr() as *const u32).read_volatile() };
+        trace!("Fetched response rx_len = {rx_len}, header: {:?}", xfer.hdr);
+        xfer.hdr.to_result()?;
+        xfer.rx.truncate(rx_len);
+        if rx_len > 0 {
+            self.read_payload(&mut xfer.rx, 4)?;
+        }
+        trace!(
+            "Fetched response: hdr={:?}, rx_len={}, buff={:?}",
+            xfer.hdr,
+            xfer.rx.len(),
+            xfer.rx
+        );
+
+        Ok(())
+    }
+
+    /// Reads a message sent by the platform on a P2A SMT channel.
+    ///
+    /// The channel is handed back to the platform with [`Shmem::clear_channel`]
+    /// once the caller has acknowledged it.
+    pub fn fetch_notification(&mut self, max_msg_size: usize) -> Result<Notification, ScmiError> {
+        self.invalidate_dcache(0, self.payload_offset());
+        let len = self.header().length.get() as usize;
+        self.read_notification(len, max_msg_size)
+    }
+
+    /// Same as [`Shmem::fetch_notification`] for a MSG channel, where `len` is
+    /// the message length reported by the transport.
+    pub fn fetch_msg_notification(
+        &mut self,
+        len: usize,
+        max_msg_size: usize,
+    ) -> Result<Notification, ScmiError> {
+        self.read_notification(len, max_msg_size)
+    }
+
+    fn read_notification(
+        &mut self,
+        len: usize,
+        max_msg_size: usize,
+    ) -> Result<Notification, ScmiError> {
+        self.invalidate_dcache(0, self.payload_offset());
+        let hdr = MsgHeader::unpack(self.msg_header());
+        // No status word in notifications.
+        let max = max_msg_size.min(self.max_payload());
+        let payload_len = match len.checked_sub(size_of::<u32>()) {
+            Some(payload_len) if payload_len <= max => payload_len,
+            _ => {
+                warn!("Invalid notification length {len}, max payload {max}");
+                return Err(ScmiError::InvalidLength(len));
+            }
+        };
+        let mut payload = Vec::new();
+        payload
+            .try_reserve_exact(payload_len)
+            .map_err(|_| ScmiError::NoMemory)?;
+        payload.resize(payload_len, 0);
+        self.read_payload(&mut payload, 0)?;
+        Ok(Notification { hdr, payload })
+    }
+
+    /// Marks the channel free again for the platform.
+    pub fn clear_channel(&mut self) {
+        if self.layout == ShmemLayout::Smt {
+            self.header().channel_status.write(ChannelStatus::FREE::SET);
+            self.clean_dcache(0, self.payload_offset());
+        }
+    }
+
+    /// Token of the message currently held in the channel.
+    pub fn token(&mut self) -> u16 {
+        self.invalidate_dcache(0, self.payload_offset());
+        MsgHeader::token_of(self.msg_header())
+    }
+
+    /// Checks whether the platform has released the channel after answering
+    /// `xfer`.
+    ///
+    /// A MSG channel has no status, only the token is checked.
+    pub fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        if self.token() != xfer.hdr.seq {
+            return false;
+        }
+        match self.layout {
+            ShmemLayout::Smt => {
+                let status = &self.header().channel_status;
+                status.is_set(ChannelStatus::FREE) || status.is_set(ChannelStatus::ERROR)
+            }
+            ShmemLayout::Msg => true,
+        }
+    }
+
+    fn msg_header(&mut self) -> u32 {
+        match self.layout {
+            ShmemLayout::Smt => self.header().msg_header.get(),
+            ShmemLayout::Msg => unsafe { (self.address.as_ptr() as *const u32).read_volatile() },
+        }
+    }
+
+    fn write_msg_header(&mut self, hdr: u32) {
+        match self.layout {
+            ShmemLayout::Smt => self.header().msg_header.set(hdr),
+            ShmemLayout::Msg => unsafe { (self.address.as_ptr() as *mut u32).write_volatile(hdr) },
+        }
+    }
+
+    /// Offset of the payload in the channel.
+    fn payload_offset(&self) -> usize {
+        Self::payload_offset_of(self.layout)
+    }
+
+    const fn payload_offset_of(layout: ShmemLayout) -> usize {
+        match layout {
+            ShmemLayout::Smt => size_of::<ShmemHeader>(),
+            ShmemLayout::Msg => size_of::<u32>(),
         }
     }
 
-    pub fn payload_ptr(&mut self) -> *mut u8 {
-        unsafe { self.address.as_ptr().add(size_of::<ShmemHeader>()) }
+    /// Room left for the payload after the header, in whole words so that
+    /// word accesses stay in the channel.
+    pub fn max_payload(&self) -> usize {
+        (self.size & !(WORD - 1)).saturating_sub(self.payload_offset())
     }
 
-    pub fn write_payload(&mut self, buff: &[u8]) {
-        unsafe {
-            let dest = self.address.as_ptr().add(size_of::<ShmemHeader>());
-            for (i, &b) in buff.iter().enumerate() {
-                dest.add(i).write_volatile(b);
+    fn payload_ptr(&mut self) -> *mut u8 {
+        unsafe { self.address.as_ptr().add(self.payload_offset()) }
+    }
+
+    /// Writes back `len` bytes at `offset` of the channel for the platform.
+    fn clean_dcache(&self, offset: usize, len: usize) {
+        if !self.cache_maintenance {
+            return;
+        }
+        #[cfg(target_arch = "aarch64")]
+        cache::dcache_range(CacheOp::Clean, self.address.as_ptr() as usize + offset, len);
+        #[cfg(not(target_arch = "aarch64"))]
+        let _ = (offset, len);
+    }
+
+    /// Drops cached copies of `len` bytes at `offset` of the channel, before
+    /// reading what the platform wrote there.
+    fn invalidate_dcache(&self, offset: usize, len: usize) {
+        if !self.cache_maintenance {
+            return;
+        }
+        #[cfg(target_arch = "aarch64")]
+        cache::dcache_range(
+            CacheOp::Invalidate,
+            self.address.as_ptr() as usize + offset,
+            len,
+        );
+        #[cfg(not(target_arch = "aarch64"))]
+        let _ = (offset, len);
+    }
+
+    /// Checks that `len` bytes at `offset` of the payload are in the channel.
+    fn check_payload(&self, offset: usize, len: usize) -> Result<(), ScmiError> {
+        let max = self.max_payload();
+        match offset.checked_add(len) {
+            Some(end) if end <= max => Ok(()),
+            _ => {
+                warn!("Payload of {len} bytes at {offset} overruns SHMEM, max payload {max}");
+                Err(ScmiError::InvalidLength(len))
+            }
+        }
+    }
+
+    /// Copies `buff` to the start of the payload.
+    pub fn write_payload(&mut self, buff: &[u8]) -> Result<(), ScmiError> {
+        self.check_payload(0, buff.len())?;
+        let access = self.access;
+        let dest = self.payload_ptr();
+        let mut done = 0;
+        // The payload offset is word aligned, so are the words of the copy.
+        for (word, range) in words(0, buff.len(), access) {
+            let src = &buff[done..done + range.len()];
+            done += range.len();
+            unsafe {
+                let at = dest.add(word);
+                match access {
+                    _ if range.len() == WORD => {
+                        let val = u32::from_ne_bytes(src.try_into().unwrap());
+                        (at as *mut u32).write_volatile(val);
+                    }
+                    ShmemAccess::U32 => {
+                        let mut val = (at as *const u32).read_volatile().to_ne_bytes();
+                        val[range].copy_from_slice(src);
+                        (at as *mut u32).write_volatile(u32::from_ne_bytes(val));
+                    }
+                    _ => {
+                        for (i, &b) in range.zip(src) {
+                            at.add(i).write_volatile(b);
+                        }
+                    }
+                }
             }
         }
         wmb();
+        self.clean_dcache(self.payload_offset(), buff.len());
+        Ok(())
     }
 
-    pub fn read_payload(&mut self, buff: &mut [u8], skip: usize) {
-        unsafe {
-            let src = self.payload_ptr();
-            for (i, b) in buff.iter_mut().enumerate() {
-                *b = src.add(skip + i).read_volatile();
+    /// Fills `buff` from the payload, starting `skip` bytes in.
+    pub fn read_payload(&mut self, buff: &mut [u8], skip: usize) -> Result<(), ScmiError> {
+        self.check_payload(skip, buff.len())?;
+        self.invalidate_dcache(self.payload_offset() + skip, buff.len());
+        let access = self.access;
+        let src = self.payload_ptr();
+        let mut done = 0;
+        for (word, range) in words(skip, buff.len(), access) {
+            let dest = &mut buff[done..done + range.len()];
+            done += range.len();
+            unsafe {
+                let at = src.add(word);
+                match access {
+                    _ if range.len() == WORD => {
+                        let val = (at as *const u32).read_volatile();
+                        dest.copy_from_slice(&val.to_ne_bytes());
+                    }
+                    ShmemAccess::U32 => {
+                        let val = (at as *const u32).read_volatile().to_ne_bytes();
+                        dest.copy_from_slice(&val[range]);
+                    }
+                    _ => {
+                        for (i, b) in range.zip(dest) {
+                            *b = at.add(i).read_volatile();
+                        }
+                    }
+                }
             }
         }
         rmb();
+        Ok(())
     }
 }
 
+const WORD: usize = size_of::<u32>();
+
+/// Splits `len` bytes at `offset` into the words they touch, as the offset of
+/// each word and the bytes of it in the copy. With [`ShmemAccess::U8`] every
+/// byte is its own chunk.
+fn words(
+    offset: usize,
+    len: usize,
+    access: ShmemAccess,
+) -> impl Iterator<Item = (usize, Range<usize>)> {
+    let end = offset + len;
+    let mut pos = offset;
+    core::iter::from_fn(move || {
+        if pos >= end {
+            return None;
+        }
+        let chunk = if access == ShmemAccess::U8 {
+            (pos, 0..1)
+        } else {
+            let word = pos & !(WORD - 1);
+            (word, pos - word..(end - word).min(WORD))
+        };
+        pos = chunk.0 + chunk.1.end;
+        Some(chunk)
+    })
+}
+
 impl Shmem {
     pub const COMPATIBLE: &str = "arm,scmi-shmem";
 }
+
+#[cfg(test)]
+mod tests {
+    use dma_api::Osal;
+
+    use super::*;
+
+    /// Identity mapped, cache coherent host memory.
+    struct HostOsal;
+
+    impl Osal for HostOsal {
+        fn map(&self, addr: NonNull<u8>, _size: usize, _direction: Direction) -> u64 {
+            addr.as_ptr() as u64
+        }
+
+        fn unmap(&self, _addr: NonNull<u8>, _size: usize) {}
+
+        fn flush(&self, _addr: NonNull<u8>, _size: usize) {}
+
+        fn invalidate(&self, _addr: NonNull<u8>, _size: usize) {}
+    }
+
+    #[test]
+    fn alloc() {
+        dma_api::init(&HostOsal);
+        let mut shmem = Shmem::alloc(0x200, ShmemLayout::Smt).unwrap();
+        assert!(shmem.is_allocated());
+        assert_eq!(shmem.bus_address, shmem.address.as_ptr() as usize);
+        assert_eq!(shmem.bus_address % Shmem::ALLOC_ALIGN, 0);
+        assert_eq!(shmem.token(), 0);
+
+        let view = shmem.msg_slice(0x100, 0x100);
+        assert!(!view.is_allocated());
+        assert_eq!(view.bus_address, shmem.bus_address + 0x100);
+    }
+
+    #[test]
+    fn new_checks_size_and_alignment() {
+        let mut mem = [0u32; 0x40];
+        let address = NonNull::new(mem.as_mut_ptr()).unwrap().cast::<u8>();
+        let min = size_of::<ShmemHeader>() + size_of::<u32>() + Shmem::MAX_MSG_SIZE;
+
+        let res = unsafe { Shmem::new(address, 0, min - 1, ShmemLayout::Smt) };
+        assert!(matches!(res, Err(ScmiError::InvalidLength(_))));
+        let res = unsafe { Shmem::new(address.add(1), 0, min, ShmemLayout::Smt) };
+        assert!(matches!(res, Err(ScmiError::InvalidParameters)));
+        assert!(unsafe { Shmem::new(address, 0, min, ShmemLayout::Smt) }.is_ok());
+    }
+
+    #[test]
+    fn payload_access_is_bounded() {
+        let mut mem = [0u32; 0x40];
+        let address = NonNull::new(mem.as_mut_ptr()).unwrap().cast::<u8>();
+        let mut shmem = unsafe { Shmem::new(address, 0, 0x100, ShmemLayout::Msg) }.unwrap();
+        let max = shmem.max_payload();
+
+        assert!(shmem.write_payload(&[0; 0xfc]).is_ok());
+        assert_eq!(
+            shmem.write_payload(&[0; 0xfd]),
+            Err(ScmiError::InvalidLength(0xfd))
+        );
+        let mut buf = [0; 8];
+        assert!(shmem.read_payload(&mut buf, max - 8).is_ok());
+        assert!(shmem.read_payload(&mut buf, max - 7).is_err());
+        assert!(shmem.read_payload(&mut buf, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn copies_in_every_access_width() {
+        let data: Vec<u8> = (1..=11).collect();
+        for access in [ShmemAccess::Auto, ShmemAccess::U8, ShmemAccess::U32] {
+            let mut mem = [0u32; 0x40];
+            let address = NonNull::new(mem.as_mut_ptr()).unwrap().cast::<u8>();
+            let mut shmem = unsafe { Shmem::new(address, 0, 0x100, ShmemLayout::Msg) }.unwrap();
+            shmem.set_access(access);
+            shmem.write_payload(&[0xff; 16]).unwrap();
+            shmem.write_payload(&data).unwrap();
+
+            let mut buf = [0; 16];
+            shmem.read_payload(&mut buf, 0).unwrap();
+            assert_eq!(buf[..11], data[..], "{access:?}");
+            assert_eq!(buf[11..], [0xff; 5], "{access:?}");
+
+            let mut buf = [0; 6];
+            shmem.read_payload(&mut buf, 3).unwrap();
+            assert_eq!(buf, [4, 5, 6, 7, 8, 9], "{access:?}");
+        }
+    }
+
+    #[test]
+    fn poll_done_follows_channel_status() {
+        let mut mem = [0u32; 0x40];
+        let address = NonNull::new(mem.as_mut_ptr()).unwrap().cast::<u8>();
+        let mut shmem = unsafe { Shmem::new(address, 0, 0x100, ShmemLayout::Smt) }.unwrap();
+        let mut xfer = Xfer::new(0x3, 0).unwrap();
+        xfer.hdr.seq = 5;
+        shmem.tx_prepare(&xfer).unwrap();
+        assert!(!shmem.poll_done(&xfer));
+
+        shmem
+            .header()
+            .channel_status
+            .write(ChannelStatus::ERROR::SET);
+        assert!(shmem.poll_done(&xfer));
+        shmem
+            .header()
+            .channel_status
+            .write(ChannelStatus::FREE::SET);
+        assert!(shmem.poll_done(&xfer));
+
+        xfer.hdr.seq = 6;
+        assert!(!shmem.poll_done(&xfer), "answer to another token");
+    }
+
+    #[test]
+    fn alloc_outside_dma_mask() {
+        dma_api::init(&HostOsal);
+        let res = Shmem::alloc_with_mask(0x200, ShmemLayout::Smt, 0xfff);
+        assert!(matches!(res, Err(ScmiError::NoMemory)));
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    Conduit, Shmem, Transport, Xfer,
+    err::{FfaError, ScmiError},
+};
+
+const FFA_ERROR: u32 = 0x8400_0060;
+const FFA_INTERRUPT: u32 = 0x8400_0062;
+const FFA_YIELD: u32 = 0x8400_006C;
+const FFA_RUN: u32 = 0x8400_006D;
+const FFA_MSG_SEND_DIRECT_REQ: u32 = 0x8400_006F;
+const FFA_MSG_SEND_DIRECT_RESP: u32 = 0x8400_0070;
+const FFA_MSG_SEND_DIRECT_RESP_64: u32 = 0xC400_0070;
+
+const MAX_MSG_SIZE: usize = 128;
+
+/// FF-A messaging used by [`Ffa`].
+///
+/// [`FfaSmc`] issues the calls to the SPMC; tests can plug a mocked partition
+/// instead.
+pub trait FfaAbi {
+    /// Sends a direct request carrying `args` in `w3`-`w7` from endpoint `src`
+    /// to partition `dest`, and returns `w3`-`w7` of its direct response.
+    fn msg_send_direct_req(
+        &mut self,
+        src: u16,
+        dest: u16,
+        args: [u32; 5],
+    ) -> Result<[u32; 5], ScmiError>;
+}
+
+/// [`FfaAbi`] over SMC/HVC calls to the SPMC.
+pub struct FfaSmc {
+    conduit: Conduit,
+}
+
+impl FfaSmc {
+    pub fn new(conduit: Conduit) -> Self {
+        FfaSmc { conduit }
+    }
+}
+
+impl FfaAbi for FfaSmc {
+    fn msg_send_direct_req(
+        &mut self,
+        src: u16,
+        dest: u16,
+        args: [u32; 5],
+    ) -> Result<[u32; 5], ScmiError> {
+        let mut regs = [0; 17];
+        regs[0] = ((src as u64) << 16) | dest as u64;
+        for (reg, arg) in regs[2..7].iter_mut().zip(args) {
+            *reg = arg as u64;
+        }
+
+        let mut func = FFA_MSG_SEND_DIRECT_REQ;
+        loop {
+            let ret = self.conduit.call64(func, regs);
+            match ret[0] as u32 {
+                FFA_MSG_SEND_DIRECT_RESP | FFA_MSG_SEND_DIRECT_RESP_64 => {
+                    let mut resp = [0; 5];
+                    for (r, reg) in resp.iter_mut().zip(&ret[3..8]) {
+                        *r = *reg as u32;
+                    }
+                    return Ok(resp);
+                }
+                FFA_ERROR => return Err(FfaError::from(ret[2] as i32).into()),
+                // The partition was preempted, let it run to completion.
+                FFA_INTERRUPT | FFA_YIELD => {
+                    func = FFA_RUN;
+                    regs = [0; 17];
+                    regs[0] = ret[1];
+                }
+                other => {
+                    error!("Unexpected FF-A response {other:#x} from partition {dest:#x}");
+                    return Err(ScmiError::TransportFailure);
+                }
+            }
+        }
+    }
+}
+
+/// FF-A transport.
+///
+/// Messages are laid out in an SMT shared memory area shared with the secure
+/// partition `dest`, and a direct request with the channel id in `w3` asks
+/// it to process them. The partition answers with an SCMI status in `w3`
+/// once the response is in place.
+///
+/// The area can be allocated with [`Shmem::alloc`] and shared with the
+/// partition beforehand.
+///
+/// Partitions are discovered through FF-A rather than the device tree, so
+/// unlike the other transports this one has no `compatible`.
+pub struct Ffa<A: FfaAbi> {
+    abi: A,
+    src: u16,
+    dest: u16,
+    channel_id: u32,
+    shmem: Shmem,
+}
+
+impl<A: FfaAbi> Ffa<A> {
+    pub fn new(abi: A, src: u16, dest: u16, channel_id: u32, mut shmem: Shmem) -> Self {
+        shmem.reset();
+        Ffa {
+            abi,
+            src,
+            dest,
+            channel_id,
+            shmem,
+        }
+    }
+}
+
+impl<A: FfaAbi> Transport for Ffa<A> {
+    fn max_msg(&self) -> usize {
+        1
+    }
+
+    fn max_msg_size(&self) -> usize {
+        MAX_MSG_SIZE
+    }
+
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        true
+    }
+
+    fn chan_available(&self, idx: usize) -> bool {
+        idx == 0
+    }
+
+    fn no_completion_irq(&self) -> bool {
+        true
+    }
+
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        self.shmem.tx_prepare(xfer)?;
+        trace!("Sending FF-A message {:?}", xfer.hdr);
+        let resp = self
+            .abi
+            .msg_send_direct_req(self.src, self.dest, [self.channel_id, 0, 0, 0, 0])
+            .inspect_err(|e| error!("FF-A request to {:#x} failed: {e}", self.dest))?;
+        ScmiError::from_status(resp[0] as i32)
+    }
+
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        self.shmem.poll_done(xfer)
+    }
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        self.shmem.fetch_response(xfer, MAX_MSG_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{vec, vec::Vec};
+    use core::ptr::NonNull;
+    use tock_registers::interfaces::{Readable, Writeable};
+
+    use super::*;
+    use crate::{
+        MsgHeader, Platform, Scmi, ShmemLayout,
+        emulator::{EmulatedClock, Emulator},
+    };
+
+    const SRC: u16 = 0x1;
+    const DEST: u16 = 0x8001;
+    const CHANNEL: u32 = 3;
+    const SHMEM_SIZE: usize = 0x100;
+
+    /// Secure partition answering direct requests from an [`Emulator`],
+    /// through its own [`Shmem`] view of the channel.
+    struct FakePartition {
+        emulator: Emulator,
+        smt: Shmem,
+        /// Returned by the SPMC instead of delivering the request.
+        error: Option<FfaError>,
+    }
+
+    impl FakePartition {
+        fn process(&mut self) {
+            let hdr = MsgHeader::unpack(self.smt.header().msg_header.get());
+            let len = self.smt.header().length.get() as usize - size_of::<u32>();
+            let mut req = vec![0; len];
+            self.smt.read_payload(&mut req, 0).unwrap();
+
+            let (status, resp) = match self.emulator.handle(hdr.protocol_id, hdr.id, &req) {
+                Ok(resp) => (ScmiError::SUCCESS, resp),
+                Err(status) => (status, Vec::new()),
+            };
+            let mut answer = Vec::new();
+            answer.extend_from_slice(&status.to_le_bytes());
+            answer.extend_from_slice(&resp);
+            self.smt.write_payload(&answer).unwrap();
+            self.smt
+                .header()
+                .length
+                .set((size_of::<u32>() + answer.len()) as u32);
+            self.smt.clear_channel();
+        }
+    }
+
+    impl FfaAbi for FakePartition {
+        fn msg_send_direct_req(
+            &mut self,
+            src: u16,
+            dest: u16,
+            args: [u32; 5],
+        ) -> Result<[u32; 5], ScmiError> {
+            if let Some(e) = self.error {
+                return Err(e.into());
+            }
+            assert_eq!((src, dest), (SRC, DEST));
+            if args[0] != CHANNEL {
+                // SCMI_ERR_PARAMS
+                return Ok([-2i32 as u32, 0, 0, 0, 0]);
+            }
+            self.process();
+            Ok([ScmiError::SUCCESS as u32, 0, 0, 0, 0])
+        }
+    }
+
+    /// An FF-A transport on `channel_id`, over a heap channel shared with a
+    /// [`FakePartition`].
+    fn ffa(mem: &mut [u32], channel_id: u32) -> Ffa<FakePartition> {
+        let address = NonNull::from(mem).cast();
+        let view = || unsafe {
+            Shmem::new(
+                address,
+                address.as_ptr() as usize,
+                SHMEM_SIZE,
+                ShmemLayout::Smt,
+            )
+            .unwrap()
+        };
+        let partition = FakePartition {
+            emulator: Emulator {
+                clocks: vec![EmulatedClock::new("cpu", 1_200_000_000)],
+                ..Default::default()
+            },
+            smt: view(),
+            error: None,
+        };
+        Ffa::new(partition, SRC, DEST, channel_id, view())
+    }
+
+    fn rate_get(clock_id: u32) -> Xfer {
+        let mut xfer = Xfer::new(0x6, MAX_MSG_SIZE).unwrap();
+        xfer.hdr.protocol_id = 0x14;
+        xfer.tx.extend_from_slice(&clock_id.to_le_bytes());
+        xfer
+    }
+
+    #[test]
+    fn direct_request() {
+        let mut mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let scmi = Scmi::new(ffa(&mut mem, CHANNEL)).unwrap();
+        let mut clk = scmi.protocol_clk().unwrap();
+        assert_eq!(clk.rate_get(0), Ok(1_200_000_000));
+        assert_eq!(clk.rate_get(1), Err(ScmiError::NotFound));
+
+        // The channel is left free, still naming the last command so that a
+        // late interrupt is not mistaken for another transfer.
+        assert_eq!(mem[1] & 1, 1);
+        let hdr = MsgHeader::unpack(mem[6]);
+        assert_eq!((hdr.protocol_id, hdr.id), (0x14, 0x6));
+    }
+
+    #[test]
+    fn ffa_error() {
+        let mut mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let mut ffa = ffa(&mut mem, CHANNEL);
+        ffa.abi.error = Some(FfaError::Busy);
+        assert_eq!(
+            ffa.send_message(&rate_get(0)),
+            Err(ScmiError::Ffa(FfaError::Busy))
+        );
+    }
+
+    #[test]
+    fn scmi_error_status() {
+        let mut mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let mut ffa = ffa(&mut mem, CHANNEL + 1);
+        assert_eq!(
+            ffa.send_message(&rate_get(0)),
+            Err(ScmiError::InvalidParameters)
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use core::{
+    cell::{RefCell, RefMut},
+    ptr::NonNull,
+};
+
+use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+use tock_registers::interfaces::{Readable, Writeable};
+
+use crate::{Shmem, ShmemLayout, Transport, Xfer, err::ScmiError, protocol::MsgHeader};
+
+const MAX_MSG_SIZE: usize = 128;
+
+/// Platform side of a [`Loopback`] transport.
+pub trait Platform {
+    /// Handles `msg_id` of `protocol_id` with `req` as payload, and returns
+    /// the response payload or a failing SCMI status.
+    fn handle(&mut self, protocol_id: u8, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, i32>;
+}
+
+struct LoopbackState<P> {
+    platform: P,
+    shmem: Shmem,
+    // Backs `shmem`, kept last so that it outlives it.
+    _mem: Box<[u32]>,
+}
+
+/// Transport handing every command to an in-process [`Platform`], for tests
+/// running on the host.
+///
+/// Commands and answers go through a heap allocated SMT channel, so they
+/// are checked by the same [`Shmem`] code as on hardware. Clones share the
+/// platform: keep one to inspect it after handing the transport to
+/// [`Scmi::new`](crate::Scmi::new).
+pub struct Loopback<P: Platform> {
+    state: Rc<RefCell<LoopbackState<P>>>,
+}
+
+impl<P: Platform> Clone for Loopback<P> {
+    fn clone(&self) -> Self {
+        Loopback {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<P: Platform> Loopback<P> {
+    pub const SHMEM_SIZE: usize = 0x100;
+
+    pub fn new(platform: P) -> Self {
+        let mut mem = vec![0u32; Self::SHMEM_SIZE / size_of::<u32>()].into_boxed_slice();
+        let address = NonNull::new(mem.as_mut_ptr()).unwrap().cast();
+        let bus_address = address.as_ptr() as usize;
+        let mut shmem =
+            unsafe { Shmem::new(address, bus_address, Self::SHMEM_SIZE, ShmemLayout::Smt) }
+                .expect("loopback channel too small");
+        shmem.reset();
+        Loopback {
+            state: Rc::new(RefCell::new(LoopbackState {
+                platform,
+                shmem,
+                _mem: mem,
+            })),
+        }
+    }
+
+    pub fn platform(&self) -> RefMut<'_, P> {
+        RefMut::map(self.state.borrow_mut(), |state| &mut state.platform)
+    }
+}
+
+impl<P: Platform> LoopbackState<P> {
+    /// Reads the command in the channel, lets the platform handle it and
+    /// writes the answer back.
+    fn answer(&mut self) {
+        let hdr = MsgHeader::unpack(self.shmem.header().msg_header.get());
+        let len = self.shmem.header().length.get() as usize - size_of::<u32>();
+        let mut req = vec![0; len];
+        self.shmem
+            .read_payload(&mut req, 0)
+            .expect("command overruns the channel");
+
+        let (status, resp) = match self.platform.handle(hdr.protocol_id, hdr.id, &req) {
+            Ok(resp) => (ScmiError::SUCCESS, resp),
+            Err(status) => (status, Vec::new()),
+        };
+        let mut answer = Vec::with_capacity(size_of::<u32>() + resp.len());
+        answer.extend_from_slice(&status.to_le_bytes());
+        answer.extend_from_slice(&resp);
+        if self.shmem.write_payload(&answer).is_err() {
+            panic!(
+                "response to message {:#x} of protocol {:#x} does not fit in the channel",
+                hdr.id, hdr.protocol_id
+            );
+        }
+        self.shmem
+            .header()
+            .length
+            .set((size_of::<u32>() + answer.len()) as u32);
+        self.shmem.clear_channel();
+    }
+}
+
+impl<P: Platform> Transport for Loopback<P> {
+    fn max_msg(&self) -> usize {
+        1
+    }
+
+    fn max_msg_size(&self) -> usize {
+        MAX_MSG_SIZE
+    }
+
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        true
+    }
+
+    fn chan_available(&self, idx: usize) -> bool {
+        idx == 0
+    }
+
+    fn no_completion_irq(&self) -> bool {
+        true
+    }
+
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        let mut state = self.state.borrow_mut();
+        state.shmem.tx_prepare(xfer)?;
+        state.answer();
+        Ok(())
+    }
+
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        self.state.borrow_mut().shmem.poll_done(xfer)
+    }
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        self.state
+            .borrow_mut()
+            .shmem
+            .fetch_response(xfer, MAX_MSG_SIZE)
+    }
+
+    fn completed_token(&mut self) -> Option<u16> {
+        Some(self.state.borrow_mut().shmem.token())
+    }
+}
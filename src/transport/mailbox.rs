@@ -0,0 +1,301 @@
+use crate::{Shmem, Transport, Xfer, err::ScmiError, protocol::Notification};
+
+const MAX_MSG_SIZE: usize = 128;
+
+/// One direction of a mailbox controller channel.
+///
+/// Implemented by the embedder on top of their mailbox controller driver;
+/// the payload itself always travels through [`Shmem`].
+pub trait Doorbell {
+    /// Signals the other side that a message is waiting in the shared
+    /// memory.
+    fn ring_tx(&mut self) -> Result<(), ScmiError>;
+
+    /// Whether the other side has not consumed the last doorbell yet.
+    fn tx_pending(&self) -> bool {
+        false
+    }
+
+    /// Acknowledges a doorbell rung by the other side.
+    fn ack_rx(&mut self);
+
+    /// Interrupt raised when the other side rings this channel, if the
+    /// controller has one.
+    fn rx_irq(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Mailbox transport (`arm,scmi`).
+///
+/// Commands go through the A2P shared memory channel, signalled with the
+/// `tx` doorbell. Notifications and delayed responses
+/// arrive on an optional separate P2A channel with its own shared memory.
+pub struct Mailbox<D: Doorbell> {
+    tx: D,
+    shmem: Shmem,
+    rx: Option<(D, Shmem)>,
+}
+
+impl<D: Doorbell> Mailbox<D> {
+    pub const COMPATIBLE: &str = "arm,scmi";
+
+    pub fn new(tx: D, mut shmem: Shmem) -> Self {
+        shmem.reset();
+        Mailbox {
+            tx,
+            shmem,
+            rx: None,
+        }
+    }
+
+    /// Builds the transport for a node compatible with `compatible`, or
+    /// fails with [`ScmiError::NotSupported`].
+    pub fn from_compatible(compatible: &str, tx: D, shmem: Shmem) -> Result<Self, ScmiError> {
+        if compatible != Self::COMPATIBLE {
+            return Err(ScmiError::NotSupported);
+        }
+        Ok(Self::new(tx, shmem))
+    }
+
+    /// Adds the P2A channel used for notifications.
+    pub fn with_rx_channel(mut self, rx: D, mut shmem: Shmem) -> Self {
+        shmem.reset();
+        shmem.clear_channel();
+        self.rx = Some((rx, shmem));
+        self
+    }
+
+    /// Interrupt raised by the platform on the P2A channel, if any.
+    pub fn rx_irq(&self) -> Option<u32> {
+        self.rx.as_ref().and_then(|(rx, _)| rx.rx_irq())
+    }
+}
+
+impl<D: Doorbell> Transport for Mailbox<D> {
+    fn max_msg(&self) -> usize {
+        1
+    }
+
+    fn max_msg_size(&self) -> usize {
+        MAX_MSG_SIZE
+    }
+
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        false
+    }
+
+    fn chan_available(&self, idx: usize) -> bool {
+        match idx {
+            0 => true,
+            1 => self.rx.is_some(),
+            _ => false,
+        }
+    }
+
+    fn no_completion_irq(&self) -> bool {
+        self.tx.rx_irq().is_none()
+    }
+
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        // Check before overwriting the channel the platform may still read.
+        if self.tx.tx_pending() {
+            return Err(ScmiError::Busy);
+        }
+        self.shmem.tx_prepare(xfer)?;
+        trace!("Sending mailbox message {:?}", xfer.hdr);
+        self.tx.ring_tx()
+    }
+
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        self.shmem.poll_done(xfer)
+    }
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        let res = self.shmem.fetch_response(xfer, MAX_MSG_SIZE);
+        self.tx.ack_rx();
+        res
+    }
+
+    fn completed_token(&mut self) -> Option<u16> {
+        Some(self.shmem.token())
+    }
+
+    fn fetch_notification(&mut self) -> Result<Option<Notification>, ScmiError> {
+        let Some((rx, shmem)) = self.rx.as_mut() else {
+            return Ok(None);
+        };
+        let res = shmem.fetch_notification(MAX_MSG_SIZE);
+        shmem.clear_channel();
+        rx.ack_rx();
+        res.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{rc::Rc, vec, vec::Vec};
+    use core::{cell::Cell, ptr::NonNull};
+    use tock_registers::interfaces::{Readable, Writeable};
+
+    use super::*;
+    use crate::{
+        MsgHeader, MsgType, Platform, Scmi, ShmemLayout,
+        emulator::{EmulatedClock, Emulator},
+    };
+
+    const SHMEM_SIZE: usize = 0x100;
+    const CLOCK: u8 = 0x14;
+
+    /// Mailbox channel whose far end answers commands from an [`Emulator`]
+    /// as soon as the agent rings it.
+    struct FakeDoorbell {
+        /// Platform side of the A2P channel, if this is its doorbell.
+        platform: Option<(Emulator, Shmem)>,
+        irq: Option<u32>,
+        rung: Rc<Cell<usize>>,
+        acked: Rc<Cell<usize>>,
+    }
+
+    impl FakeDoorbell {
+        fn new(irq: Option<u32>) -> Self {
+            FakeDoorbell {
+                platform: None,
+                irq,
+                rung: Rc::new(Cell::new(0)),
+                acked: Rc::new(Cell::new(0)),
+            }
+        }
+
+        fn answering(smt: Shmem) -> Self {
+            let emulator = Emulator {
+                clocks: vec![EmulatedClock::new("cpu", 1_200_000_000)],
+                ..Default::default()
+            };
+            FakeDoorbell {
+                platform: Some((emulator, smt)),
+                ..Self::new(None)
+            }
+        }
+    }
+
+    impl Doorbell for FakeDoorbell {
+        fn ring_tx(&mut self) -> Result<(), ScmiError> {
+            self.rung.set(self.rung.get() + 1);
+            let Some((emulator, smt)) = self.platform.as_mut() else {
+                return Ok(());
+            };
+            let hdr = MsgHeader::unpack(smt.header().msg_header.get());
+            let len = smt.header().length.get() as usize - size_of::<u32>();
+            let mut req = vec![0; len];
+            smt.read_payload(&mut req, 0).unwrap();
+
+            let (status, resp) = match emulator.handle(hdr.protocol_id, hdr.id, &req) {
+                Ok(resp) => (ScmiError::SUCCESS, resp),
+                Err(status) => (status, Vec::new()),
+            };
+            let mut answer = Vec::new();
+            answer.extend_from_slice(&status.to_le_bytes());
+            answer.extend_from_slice(&resp);
+            smt.write_payload(&answer).unwrap();
+            smt.header()
+                .length
+                .set((size_of::<u32>() + answer.len()) as u32);
+            smt.clear_channel();
+            Ok(())
+        }
+
+        fn ack_rx(&mut self) {
+            self.acked.set(self.acked.get() + 1);
+        }
+
+        fn rx_irq(&self) -> Option<u32> {
+            self.irq
+        }
+    }
+
+    /// Two views of one heap SMT channel: the agent's and the platform's.
+    fn channel(mem: &mut [u32]) -> (Shmem, Shmem) {
+        let address = NonNull::from(mem).cast();
+        let view = || unsafe {
+            Shmem::new(
+                address,
+                address.as_ptr() as usize,
+                SHMEM_SIZE,
+                ShmemLayout::Smt,
+            )
+            .unwrap()
+        };
+        (view(), view())
+    }
+
+    #[test]
+    fn commands_through_emulator() {
+        let mut mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let (agent, platform) = channel(&mut mem);
+        let tx = FakeDoorbell::answering(platform);
+        let (rung, acked) = (tx.rung.clone(), tx.acked.clone());
+
+        let scmi = Scmi::new(Mailbox::new(tx, agent)).unwrap();
+        let mut clk = scmi.protocol_clk().unwrap();
+        clk.rate_set(0, 800_000_000).unwrap();
+        assert_eq!(clk.rate_get(0), Ok(800_000_000));
+        assert_eq!(clk.rate_get(1), Err(ScmiError::NotFound));
+        assert_eq!(rung.get(), acked.get(), "every answer acknowledged");
+        assert!(matches!(scmi.fetch_notification(), Ok(None)));
+    }
+
+    #[test]
+    fn no_completion_irq_follows_rx_irq() {
+        let mut mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let (shmem, _) = channel(&mut mem);
+        let mailbox = Mailbox::new(FakeDoorbell::new(None), shmem);
+        assert!(mailbox.no_completion_irq());
+        assert_eq!(mailbox.rx_irq(), None);
+        assert!(!mailbox.chan_available(1));
+
+        let (shmem, rx_shmem) = channel(&mut mem);
+        let mailbox = Mailbox::new(FakeDoorbell::new(Some(33)), shmem)
+            .with_rx_channel(FakeDoorbell::new(Some(34)), rx_shmem);
+        assert!(!mailbox.no_completion_irq());
+        assert_eq!(mailbox.rx_irq(), Some(34));
+        assert!(mailbox.chan_available(1));
+    }
+
+    #[test]
+    fn notification_on_rx_channel() {
+        let mut mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let mut rx_mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let (agent, platform) = channel(&mut mem);
+        let (rx_agent, mut rx_platform) = channel(&mut rx_mem);
+        let rx = FakeDoorbell::new(Some(34));
+        let acked = rx.acked.clone();
+        let mailbox =
+            Mailbox::new(FakeDoorbell::answering(platform), agent).with_rx_channel(rx, rx_agent);
+        let scmi = Scmi::new(mailbox).unwrap();
+
+        let hdr = MsgHeader {
+            id: 0x1,
+            protocol_id: CLOCK,
+            type_: MsgType::Notification,
+            ..Default::default()
+        };
+        let payload = 7u32.to_le_bytes();
+        rx_platform.header().msg_header.set(hdr.pack());
+        rx_platform
+            .header()
+            .length
+            .set((size_of::<u32>() + payload.len()) as u32);
+        rx_platform.write_payload(&payload).unwrap();
+
+        let notif = scmi.fetch_notification().unwrap().unwrap();
+        assert_eq!(notif.hdr.protocol_id, CLOCK);
+        assert_eq!(notif.hdr.id, 0x1);
+        assert_eq!(notif.hdr.type_, MsgType::Notification);
+        assert_eq!(notif.payload, payload);
+        assert_eq!(acked.get(), 1);
+        // Channel handed back to the platform.
+        let status = unsafe { rx_mem.as_ptr().add(1).read_volatile() };
+        assert_eq!(status & 1, 1);
+    }
+}
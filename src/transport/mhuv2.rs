@@ -0,0 +1,323 @@
+use core::{hint::spin_loop, ptr::NonNull};
+
+use tock_registers::{interfaces::*, registers::*};
+
+use super::Doorbell;
+use crate::err::ScmiError;
+
+const MHUV2_CHANNELS: usize = 124;
+const MHUV2_ARCH_MAJOR: u32 = 1;
+/// Reads of `ACCESS_READY` before giving up on the receiver waking up.
+const ACCESS_READY_POLLS: usize = 1_000_000;
+
+tock_registers::register_structs! {
+    pub Mhuv2SendChannel {
+        (0x00 => st: ReadOnly<u32>),
+        (0x04 => reserved0: [u32; 2]),
+        (0x0C => st_set: WriteOnly<u32>),
+        (0x10 => reserved1: [u32; 4]),
+        (0x20 => @END),
+    },
+
+    pub Mhuv2SendFrame {
+        (0x000 => channel: [Mhuv2SendChannel; MHUV2_CHANNELS]),
+        (0xF80 => mhu_cfg: ReadOnly<u32, MhuCfg::Register>),
+        (0xF84 => resp_cfg: ReadWrite<u32>),
+        (0xF88 => access_request: ReadWrite<u32>),
+        (0xF8C => access_ready: ReadOnly<u32>),
+        (0xF90 => int_st: ReadOnly<u32>),
+        (0xF94 => int_clr: WriteOnly<u32>),
+        (0xF98 => int_en: ReadWrite<u32>),
+        (0xF9C => reserved0: u32),
+        (0xFA0 => chcomb_int_st: [ReadOnly<u32>; 4]),
+        (0xFB0 => reserved1: [u32; 6]),
+        (0xFC8 => iidr: ReadOnly<u32>),
+        (0xFCC => aidr: ReadOnly<u32, Aidr::Register>),
+        (0xFD0 => reserved2: [u32; 12]),
+        (0x1000 => @END),
+    },
+
+    pub Mhuv2RecvChannel {
+        (0x00 => st: ReadOnly<u32>),
+        (0x04 => st_masked: ReadOnly<u32>),
+        (0x08 => st_clear: WriteOnly<u32>),
+        (0x0C => reserved0: u32),
+        (0x10 => mask: ReadOnly<u32>),
+        (0x14 => mask_set: WriteOnly<u32>),
+        (0x18 => mask_clear: WriteOnly<u32>),
+        (0x1C => reserved1: u32),
+        (0x20 => @END),
+    },
+
+    pub Mhuv2RecvFrame {
+        (0x000 => channel: [Mhuv2RecvChannel; MHUV2_CHANNELS]),
+        (0xF80 => mhu_cfg: ReadOnly<u32, MhuCfg::Register>),
+        (0xF84 => reserved0: [u32; 3]),
+        (0xF90 => int_st: ReadOnly<u32>),
+        (0xF94 => int_clr: WriteOnly<u32>),
+        (0xF98 => int_en: ReadWrite<u32, IntEn::Register>),
+        (0xF9C => reserved1: u32),
+        (0xFA0 => chcomb_int_st: [ReadOnly<u32>; 4]),
+        (0xFB0 => reserved2: [u32; 6]),
+        (0xFC8 => iidr: ReadOnly<u32>),
+        (0xFCC => aidr: ReadOnly<u32, Aidr::Register>),
+        (0xFD0 => reserved3: [u32; 12]),
+        (0x1000 => @END),
+    }
+}
+
+tock_registers::register_bitfields![
+    u32,
+    MhuCfg [
+        NUM_CH OFFSET(0) NUMBITS(7) [],
+    ],
+    IntEn [
+        CHCOMB OFFSET(2) NUMBITS(1) [],
+    ],
+    Aidr [
+        ARCH_MINOR_REV OFFSET(0) NUMBITS(4) [],
+        ARCH_MAJOR_REV OFFSET(4) NUMBITS(4) [],
+    ],
+];
+
+/// ARM MHUv2 in doorbell mode.
+///
+/// `ring_tx` sets bit `doorbell` of channel `channel` in the sender frame;
+/// the platform answers on the same channel and bit of the receiver frame.
+pub struct Mhuv2Doorbell {
+    send: NonNull<Mhuv2SendFrame>,
+    recv: Option<NonNull<Mhuv2RecvFrame>>,
+    channel: usize,
+    mask: u32,
+    irq: Option<u32>,
+}
+
+impl Mhuv2Doorbell {
+    pub const COMPATIBLE: &str = "arm,mhuv2-tx";
+    pub const COMPATIBLE_RX: &str = "arm,mhuv2-rx";
+
+    /// Opens doorbell `doorbell` of `channel` on a sender frame.
+    ///
+    /// # Safety
+    ///
+    /// `send` must be the mapped MMIO base of an MHUv2 sender frame, and that
+    /// channel bit must not be driven by anyone else.
+    ///
+    /// Fails with [`ScmiError::TransportFailure`] if the receiver does not
+    /// acknowledge the access request.
+    pub unsafe fn new(send: NonNull<u8>, channel: usize, doorbell: u32) -> Result<Self, ScmiError> {
+        let send = send.cast::<Mhuv2SendFrame>();
+        let frame = unsafe { send.as_ref() };
+        check_frame(&frame.aidr, &frame.mhu_cfg, channel, doorbell)?;
+
+        // Keep the receiver awake for as long as we may ring it.
+        frame.access_request.set(1);
+        let mut polls = 0;
+        while frame.access_ready.get() == 0 {
+            if polls == ACCESS_READY_POLLS {
+                error!("MHUv2 receiver of channel {channel} did not wake up");
+                frame.access_request.set(0);
+                return Err(ScmiError::TransportFailure);
+            }
+            polls += 1;
+            spin_loop();
+        }
+
+        Ok(Self {
+            send,
+            recv: None,
+            channel,
+            mask: 1 << doorbell,
+            irq: None,
+        })
+    }
+
+    /// Adds the receiver frame on which the platform signals completion.
+    ///
+    /// # Safety
+    ///
+    /// `recv` must be the mapped MMIO base of an MHUv2 receiver frame, and
+    /// the same channel bit must not be handled by anyone else.
+    pub unsafe fn with_receiver(
+        mut self,
+        recv: NonNull<u8>,
+        irq: Option<u32>,
+    ) -> Result<Self, ScmiError> {
+        let recv = recv.cast::<Mhuv2RecvFrame>();
+        let frame = unsafe { recv.as_ref() };
+        check_frame(
+            &frame.aidr,
+            &frame.mhu_cfg,
+            self.channel,
+            self.mask.trailing_zeros(),
+        )?;
+
+        let ch = &frame.channel[self.channel];
+        ch.st_clear.set(self.mask);
+        ch.mask_clear.set(self.mask);
+        if frame.aidr.read(Aidr::ARCH_MINOR_REV) > 0 {
+            frame.int_en.modify(IntEn::CHCOMB::SET);
+        }
+
+        self.recv = Some(recv);
+        self.irq = irq;
+        Ok(self)
+    }
+
+    fn send(&self) -> &Mhuv2SendFrame {
+        unsafe { self.send.as_ref() }
+    }
+}
+
+fn check_frame(
+    aidr: &ReadOnly<u32, Aidr::Register>,
+    mhu_cfg: &ReadOnly<u32, MhuCfg::Register>,
+    channel: usize,
+    doorbell: u32,
+) -> Result<(), ScmiError> {
+    let major = aidr.read(Aidr::ARCH_MAJOR_REV);
+    if major != MHUV2_ARCH_MAJOR {
+        error!("Not an MHUv2 frame, arch major revision {major}");
+        return Err(ScmiError::NotSupported);
+    }
+    let num_ch = mhu_cfg.read(MhuCfg::NUM_CH) as usize;
+    if channel >= num_ch || doorbell >= u32::BITS {
+        error!("MHUv2 doorbell {channel}:{doorbell} out of range, {num_ch} channels");
+        return Err(ScmiError::InvalidParameters);
+    }
+    Ok(())
+}
+
+impl Doorbell for Mhuv2Doorbell {
+    fn ring_tx(&mut self) -> Result<(), ScmiError> {
+        if self.tx_pending() {
+            return Err(ScmiError::Busy);
+        }
+        self.send().channel[self.channel].st_set.set(self.mask);
+        Ok(())
+    }
+
+    fn tx_pending(&self) -> bool {
+        self.send().channel[self.channel].st.get() & self.mask != 0
+    }
+
+    fn ack_rx(&mut self) {
+        if let Some(recv) = self.recv {
+            let frame = unsafe { recv.as_ref() };
+            frame.channel[self.channel].st_clear.set(self.mask);
+        }
+    }
+
+    fn rx_irq(&self) -> Option<u32> {
+        self.irq
+    }
+}
+
+impl Drop for Mhuv2Doorbell {
+    fn drop(&mut self) {
+        self.send().access_request.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec};
+
+    use super::*;
+
+    /// A register frame backed by plain memory: writes stick, nothing
+    /// reacts to them.
+    struct Frame(Box<[u32]>);
+
+    impl Frame {
+        /// A v2.1 frame with 4 channels and its receiver awake.
+        fn new() -> Self {
+            let mut frame = Frame(vec![0; size_of::<Mhuv2SendFrame>() / 4].into_boxed_slice());
+            frame.set(0xFCC, 0x11);
+            frame.set(0xF80, 4);
+            frame.set(0xF8C, 1);
+            frame
+        }
+
+        fn set(&mut self, offset: usize, val: u32) {
+            self.0[offset / 4] = val;
+        }
+
+        fn get(&self, offset: usize) -> u32 {
+            unsafe { self.0.as_ptr().add(offset / 4).read_volatile() }
+        }
+
+        fn base(&mut self) -> NonNull<u8> {
+            NonNull::new(self.0.as_mut_ptr()).unwrap().cast()
+        }
+    }
+
+    /// Offset of register `reg` of channel `ch`.
+    fn ch(ch: usize, reg: usize) -> usize {
+        ch * size_of::<Mhuv2SendChannel>() + reg
+    }
+
+    #[test]
+    fn rejects_other_architectures() {
+        let mut send = Frame::new();
+        send.set(0xFCC, 0x20);
+        let res = unsafe { Mhuv2Doorbell::new(send.base(), 0, 0) };
+        assert_eq!(res.err(), Some(ScmiError::NotSupported));
+    }
+
+    #[test]
+    fn checks_channel_count() {
+        let mut send = Frame::new();
+        let base = send.base();
+        let res = unsafe { Mhuv2Doorbell::new(base, 4, 0) };
+        assert_eq!(res.err(), Some(ScmiError::InvalidParameters));
+        let res = unsafe { Mhuv2Doorbell::new(base, 0, 32) };
+        assert_eq!(res.err(), Some(ScmiError::InvalidParameters));
+        assert!(unsafe { Mhuv2Doorbell::new(base, 3, 31) }.is_ok());
+    }
+
+    #[test]
+    fn ring_and_pending() {
+        let mut send = Frame::new();
+        let mut db = unsafe { Mhuv2Doorbell::new(send.base(), 2, 5) }.unwrap();
+        assert_eq!(send.get(0xF88), 1, "access requested");
+
+        assert!(!db.tx_pending());
+        db.ring_tx().unwrap();
+        assert_eq!(send.get(ch(2, 0x0C)), 1 << 5);
+
+        // The receiver has not taken the doorbell yet.
+        send.set(ch(2, 0x00), 1 << 5);
+        assert!(db.tx_pending());
+        assert_eq!(db.ring_tx(), Err(ScmiError::Busy));
+        send.set(ch(2, 0x00), 1 << 4);
+        assert!(!db.tx_pending());
+
+        drop(db);
+        assert_eq!(send.get(0xF88), 0, "access released");
+    }
+
+    #[test]
+    fn receiver_is_acknowledged() {
+        let mut send = Frame::new();
+        let mut recv = Frame::new();
+        let db = unsafe { Mhuv2Doorbell::new(send.base(), 1, 3) }.unwrap();
+        let mut db = unsafe { db.with_receiver(recv.base(), Some(40)) }.unwrap();
+        assert_eq!(db.rx_irq(), Some(40));
+        assert_eq!(recv.get(ch(1, 0x18)), 1 << 3, "doorbell unmasked");
+        assert_eq!(recv.get(0xF98), 1 << 2, "combined interrupt enabled");
+
+        recv.set(ch(1, 0x08), 0);
+        db.ack_rx();
+        assert_eq!(recv.get(ch(1, 0x08)), 1 << 3);
+    }
+
+    #[test]
+    fn receiver_that_never_wakes_up() {
+        let mut send = Frame::new();
+        send.set(0xF8C, 0);
+        let res = unsafe { Mhuv2Doorbell::new(send.base(), 0, 0) };
+        assert_eq!(res.err(), Some(ScmiError::TransportFailure));
+        assert_eq!(send.get(0xF88), 0);
+    }
+}
@@ -0,0 +1,330 @@
+use core::ptr::NonNull;
+
+use tock_registers::{interfaces::*, registers::*};
+
+use super::Doorbell;
+use crate::err::ScmiError;
+
+const MHUV3_DBCW_MAX: usize = 128;
+const MHUV3_ARCH_MAJOR: u32 = 2;
+
+tock_registers::register_structs! {
+    /// Control page shared by the postbox and mailbox frames.
+    pub Mhuv3CtrlPage {
+        (0x000 => blk_id: ReadOnly<u32>),
+        (0x004 => reserved0: [u32; 3]),
+        (0x010 => feat_spt0: ReadOnly<u32, FeatSpt0::Register>),
+        (0x014 => feat_spt1: ReadOnly<u32>),
+        (0x018 => reserved1: [u32; 2]),
+        (0x020 => dbch_cfg0: ReadOnly<u32, DbchCfg0::Register>),
+        (0x024 => reserved2: [u32; 3]),
+        (0x030 => ffch_cfg0: ReadOnly<u32>),
+        (0x034 => reserved3: [u32; 3]),
+        (0x040 => fch_cfg0: ReadOnly<u32>),
+        (0x044 => reserved4: [u32; 47]),
+        (0x100 => ctrl: ReadWrite<u32, XCtrl::Register>),
+        (0x104 => reserved5: [u32; 945]),
+        (0xFC8 => iidr: ReadOnly<u32>),
+        (0xFCC => aidr: ReadOnly<u32, Aidr::Register>),
+        (0xFD0 => reserved6: [u32; 12]),
+        (0x1000 => @END),
+    },
+
+    /// Postbox doorbell channel window.
+    pub Mhuv3Pdbcw {
+        (0x00 => st: ReadOnly<u32>),
+        (0x04 => reserved0: [u32; 2]),
+        (0x0C => set: WriteOnly<u32>),
+        (0x10 => int_st: ReadOnly<u32>),
+        (0x14 => int_clr: WriteOnly<u32>),
+        (0x18 => int_en: ReadWrite<u32>),
+        (0x1C => ctrl: ReadWrite<u32>),
+        (0x20 => @END),
+    },
+
+    /// Mailbox doorbell channel window.
+    pub Mhuv3Mdbcw {
+        (0x00 => st: ReadOnly<u32>),
+        (0x04 => st_msk: ReadOnly<u32>),
+        (0x08 => clr: WriteOnly<u32>),
+        (0x0C => reserved0: u32),
+        (0x10 => msk_st: ReadOnly<u32>),
+        (0x14 => msk_set: WriteOnly<u32>),
+        (0x18 => msk_clr: WriteOnly<u32>),
+        (0x1C => ctrl: ReadWrite<u32, DbcwCtrl::Register>),
+        (0x20 => @END),
+    },
+
+    pub Mhuv3PbxFrame {
+        (0x0000 => ctrl: Mhuv3CtrlPage),
+        (0x1000 => dbcw: [Mhuv3Pdbcw; MHUV3_DBCW_MAX]),
+        (0x2000 => @END),
+    },
+
+    pub Mhuv3MbxFrame {
+        (0x0000 => ctrl: Mhuv3CtrlPage),
+        (0x1000 => dbcw: [Mhuv3Mdbcw; MHUV3_DBCW_MAX]),
+        (0x2000 => @END),
+    }
+}
+
+tock_registers::register_bitfields![
+    u32,
+    FeatSpt0 [
+        DBE_SPT OFFSET(0) NUMBITS(4) [],
+    ],
+    DbchCfg0 [
+        NUM_DBCH OFFSET(0) NUMBITS(8) [],
+    ],
+    XCtrl [
+        OP_REQ OFFSET(0) NUMBITS(1) [],
+        CH_OP_MSK OFFSET(1) NUMBITS(1) [],
+    ],
+    DbcwCtrl [
+        COMB_EN OFFSET(0) NUMBITS(1) [],
+    ],
+    Aidr [
+        ARCH_MINOR_REV OFFSET(0) NUMBITS(4) [],
+        ARCH_MAJOR_REV OFFSET(4) NUMBITS(4) [],
+    ],
+];
+
+/// ARM MHUv3 doorbell extension.
+///
+/// `ring_tx` sets bit `doorbell` of doorbell channel `channel` in the
+/// postbox; the platform answers on the same channel and bit of the mailbox
+/// frame.
+pub struct Mhuv3Doorbell {
+    pbx: NonNull<Mhuv3PbxFrame>,
+    mbx: Option<NonNull<Mhuv3MbxFrame>>,
+    channel: usize,
+    mask: u32,
+    irq: Option<u32>,
+}
+
+impl Mhuv3Doorbell {
+    pub const COMPATIBLE: &str = "arm,mhuv3";
+
+    /// Opens doorbell `doorbell` of doorbell channel `channel` on a postbox
+    /// frame.
+    ///
+    /// # Safety
+    ///
+    /// `pbx` must be the mapped MMIO base of an MHUv3 postbox frame, and that
+    /// channel bit must not be driven by anyone else.
+    pub unsafe fn new(pbx: NonNull<u8>, channel: usize, doorbell: u32) -> Result<Self, ScmiError> {
+        let pbx = pbx.cast::<Mhuv3PbxFrame>();
+        let frame = unsafe { pbx.as_ref() };
+        check_ctrl(&frame.ctrl, channel, doorbell)?;
+        request_operational(&frame.ctrl);
+
+        Ok(Self {
+            pbx,
+            mbx: None,
+            channel,
+            mask: 1 << doorbell,
+            irq: None,
+        })
+    }
+
+    /// Adds the mailbox frame on which the platform signals completion.
+    ///
+    /// # Safety
+    ///
+    /// `mbx` must be the mapped MMIO base of an MHUv3 mailbox frame, and the
+    /// same channel bit must not be handled by anyone else.
+    pub unsafe fn with_receiver(
+        mut self,
+        mbx: NonNull<u8>,
+        irq: Option<u32>,
+    ) -> Result<Self, ScmiError> {
+        let mbx = mbx.cast::<Mhuv3MbxFrame>();
+        let frame = unsafe { mbx.as_ref() };
+        check_ctrl(&frame.ctrl, self.channel, self.mask.trailing_zeros())?;
+        request_operational(&frame.ctrl);
+
+        let dbcw = &frame.dbcw[self.channel];
+        dbcw.clr.set(self.mask);
+        dbcw.msk_clr.set(self.mask);
+        dbcw.ctrl.modify(DbcwCtrl::COMB_EN::SET);
+
+        self.mbx = Some(mbx);
+        self.irq = irq;
+        Ok(self)
+    }
+
+    fn pbx(&self) -> &Mhuv3PbxFrame {
+        unsafe { self.pbx.as_ref() }
+    }
+}
+
+fn check_ctrl(ctrl: &Mhuv3CtrlPage, channel: usize, doorbell: u32) -> Result<(), ScmiError> {
+    let major = ctrl.aidr.read(Aidr::ARCH_MAJOR_REV);
+    if major != MHUV3_ARCH_MAJOR {
+        error!("Not an MHUv3 frame, arch major revision {major}");
+        return Err(ScmiError::NotSupported);
+    }
+    if ctrl.feat_spt0.read(FeatSpt0::DBE_SPT) == 0 {
+        error!("MHUv3 frame without doorbell extension");
+        return Err(ScmiError::NotSupported);
+    }
+    let num_dbch = ctrl.dbch_cfg0.read(DbchCfg0::NUM_DBCH) as usize + 1;
+    if channel >= num_dbch.min(MHUV3_DBCW_MAX) || doorbell >= u32::BITS {
+        error!("MHUv3 doorbell {channel}:{doorbell} out of range, {num_dbch} channels");
+        return Err(ScmiError::InvalidParameters);
+    }
+    Ok(())
+}
+
+fn request_operational(ctrl: &Mhuv3CtrlPage) {
+    // Keep the frame operational, with the channels following OP_REQ.
+    ctrl.ctrl
+        .modify(XCtrl::OP_REQ::SET + XCtrl::CH_OP_MSK::CLEAR);
+}
+
+fn release_operational(ctrl: &Mhuv3CtrlPage) {
+    ctrl.ctrl.modify(XCtrl::OP_REQ::CLEAR);
+}
+
+impl Doorbell for Mhuv3Doorbell {
+    fn ring_tx(&mut self) -> Result<(), ScmiError> {
+        if self.tx_pending() {
+            return Err(ScmiError::Busy);
+        }
+        self.pbx().dbcw[self.channel].set.set(self.mask);
+        Ok(())
+    }
+
+    fn tx_pending(&self) -> bool {
+        self.pbx().dbcw[self.channel].st.get() & self.mask != 0
+    }
+
+    fn ack_rx(&mut self) {
+        if let Some(mbx) = self.mbx {
+            let frame = unsafe { mbx.as_ref() };
+            frame.dbcw[self.channel].clr.set(self.mask);
+        }
+    }
+
+    fn rx_irq(&self) -> Option<u32> {
+        self.irq
+    }
+}
+
+impl Drop for Mhuv3Doorbell {
+    fn drop(&mut self) {
+        // Let the frames go back to low power.
+        release_operational(&self.pbx().ctrl);
+        if let Some(mbx) = self.mbx {
+            release_operational(unsafe { &mbx.as_ref().ctrl });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec};
+
+    use super::*;
+
+    const CTRL: usize = 0x100;
+    const DBCW: usize = 0x1000;
+
+    /// A register frame backed by plain memory: writes stick, nothing
+    /// reacts to them.
+    struct Frame(Box<[u32]>);
+
+    impl Frame {
+        /// A v3.0 frame with the doorbell extension and 4 doorbell channels.
+        fn new() -> Self {
+            let mut frame = Frame(vec![0; size_of::<Mhuv3PbxFrame>() / 4].into_boxed_slice());
+            frame.set(0xFCC, 0x20);
+            frame.set(0x010, 1);
+            frame.set(0x020, 3);
+            frame
+        }
+
+        fn set(&mut self, offset: usize, val: u32) {
+            self.0[offset / 4] = val;
+        }
+
+        fn get(&self, offset: usize) -> u32 {
+            unsafe { self.0.as_ptr().add(offset / 4).read_volatile() }
+        }
+
+        fn base(&mut self) -> NonNull<u8> {
+            NonNull::new(self.0.as_mut_ptr()).unwrap().cast()
+        }
+    }
+
+    /// Offset of register `reg` of doorbell channel window `ch`.
+    fn dbcw(ch: usize, reg: usize) -> usize {
+        DBCW + ch * size_of::<Mhuv3Pdbcw>() + reg
+    }
+
+    #[test]
+    fn rejects_other_architectures() {
+        let mut pbx = Frame::new();
+        pbx.set(0xFCC, 0x11);
+        let res = unsafe { Mhuv3Doorbell::new(pbx.base(), 0, 0) };
+        assert_eq!(res.err(), Some(ScmiError::NotSupported));
+    }
+
+    #[test]
+    fn requires_doorbell_extension() {
+        let mut pbx = Frame::new();
+        pbx.set(0x010, 0);
+        let res = unsafe { Mhuv3Doorbell::new(pbx.base(), 0, 0) };
+        assert_eq!(res.err(), Some(ScmiError::NotSupported));
+    }
+
+    #[test]
+    fn checks_channel_count() {
+        let mut pbx = Frame::new();
+        let base = pbx.base();
+        let res = unsafe { Mhuv3Doorbell::new(base, 4, 0) };
+        assert_eq!(res.err(), Some(ScmiError::InvalidParameters));
+        let res = unsafe { Mhuv3Doorbell::new(base, 0, 32) };
+        assert_eq!(res.err(), Some(ScmiError::InvalidParameters));
+        assert!(unsafe { Mhuv3Doorbell::new(base, 3, 31) }.is_ok());
+    }
+
+    #[test]
+    fn ring_and_pending() {
+        let mut pbx = Frame::new();
+        pbx.set(CTRL, 1 << 1);
+        let mut db = unsafe { Mhuv3Doorbell::new(pbx.base(), 2, 5) }.unwrap();
+        assert_eq!(pbx.get(CTRL), 1, "operational, channels follow OP_REQ");
+
+        assert!(!db.tx_pending());
+        db.ring_tx().unwrap();
+        assert_eq!(pbx.get(dbcw(2, 0x0C)), 1 << 5);
+
+        // The mailbox has not taken the doorbell yet.
+        pbx.set(dbcw(2, 0x00), 1 << 5);
+        assert!(db.tx_pending());
+        assert_eq!(db.ring_tx(), Err(ScmiError::Busy));
+        pbx.set(dbcw(2, 0x00), 1 << 4);
+        assert!(!db.tx_pending());
+    }
+
+    #[test]
+    fn receiver_is_acknowledged() {
+        let mut pbx = Frame::new();
+        let mut mbx = Frame::new();
+        let db = unsafe { Mhuv3Doorbell::new(pbx.base(), 1, 3) }.unwrap();
+        let mut db = unsafe { db.with_receiver(mbx.base(), Some(40)) }.unwrap();
+        assert_eq!(db.rx_irq(), Some(40));
+        assert_eq!(mbx.get(CTRL), 1);
+        assert_eq!(mbx.get(dbcw(1, 0x18)), 1 << 3, "doorbell unmasked");
+        assert_eq!(mbx.get(dbcw(1, 0x1C)), 1, "combined interrupt enabled");
+
+        mbx.set(dbcw(1, 0x08), 0);
+        db.ack_rx();
+        assert_eq!(mbx.get(dbcw(1, 0x08)), 1 << 3);
+
+        drop(db);
+        assert_eq!(pbx.get(CTRL), 0, "postbox released");
+        assert_eq!(mbx.get(CTRL), 0, "mailbox released");
+    }
+}
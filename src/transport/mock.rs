@@ -0,0 +1,241 @@
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::{
+    Transport, Xfer,
+    codec::{Encode, Writer},
+    err::ScmiError,
+};
+
+use super::loopback::{Loopback, Platform};
+
+/// A scripted message and the platform answer to it.
+struct Exchange {
+    protocol_id: u8,
+    msg_id: u8,
+    request: Vec<u8>,
+    status: i32,
+    response: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Script {
+    exchanges: VecDeque<Exchange>,
+}
+
+impl Platform for Script {
+    fn handle(&mut self, protocol_id: u8, msg_id: u8, req: &[u8]) -> Result<Vec<u8>, i32> {
+        let Some(exchange) = self.exchanges.pop_front() else {
+            panic!("unexpected message {msg_id:#x} of protocol {protocol_id:#x}, payload {req:x?}");
+        };
+        assert_eq!(
+            (protocol_id, msg_id),
+            (exchange.protocol_id, exchange.msg_id),
+            "unexpected message, payload {req:x?}"
+        );
+        assert_eq!(
+            req, exchange.request,
+            "unexpected payload for message {msg_id:#x} of protocol {protocol_id:#x}"
+        );
+        if exchange.status == ScmiError::SUCCESS {
+            Ok(exchange.response)
+        } else {
+            Err(exchange.status)
+        }
+    }
+}
+
+/// Transport answering from a script instead of a platform, for unit tests
+/// running on the host.
+///
+/// Every message sent must be the next one scripted, with the same payload,
+/// or the transport panics. Answers go through a [`Loopback`] channel.
+///
+/// Clones share the script: keep one to add expectations and check that
+/// everything was sent after handing the transport to
+/// [`Scmi::new`](crate::Scmi::new).
+#[derive(Clone)]
+pub struct MockTransport {
+    inner: Loopback<Script>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport {
+            inner: Loopback::new(Script::default()),
+        }
+    }
+
+    /// Expects `msg_id` of `protocol_id` with `req` as payload, and answers
+    /// it successfully with `resp`.
+    pub fn expect<Q: Encode, R: Encode>(
+        &self,
+        protocol_id: u8,
+        msg_id: u8,
+        req: Q,
+        resp: R,
+    ) -> &Self {
+        self.push(protocol_id, msg_id, &req, ScmiError::SUCCESS, &resp)
+    }
+
+    /// Expects `msg_id` of `protocol_id` with `req` as payload, and fails it
+    /// with the SCMI `status`.
+    pub fn expect_status<Q: Encode>(
+        &self,
+        protocol_id: u8,
+        msg_id: u8,
+        req: Q,
+        status: i32,
+    ) -> &Self {
+        self.push(protocol_id, msg_id, &req, status, &())
+    }
+
+    /// Expects the `PROTOCOL_VERSION` query opening `protocol_id`.
+    pub fn expect_version(&self, protocol_id: u8, version: (u16, u16)) -> &Self {
+        let raw = ((version.0 as u32) << 16) | version.1 as u32;
+        self.expect(protocol_id, 0, (), raw)
+    }
+
+    /// Number of scripted messages not sent yet.
+    pub fn pending(&self) -> usize {
+        self.inner.platform().exchanges.len()
+    }
+
+    fn push(
+        &self,
+        protocol_id: u8,
+        msg_id: u8,
+        req: &dyn Encode,
+        status: i32,
+        resp: &dyn Encode,
+    ) -> &Self {
+        let mut request = Vec::new();
+        req.encode(&mut Writer::new(&mut request))
+            .expect("request does not fit in memory");
+        let mut response = Vec::new();
+        resp.encode(&mut Writer::new(&mut response))
+            .expect("response does not fit in memory");
+        self.inner.platform().exchanges.push_back(Exchange {
+            protocol_id,
+            msg_id,
+            request,
+            status,
+            response,
+        });
+        self
+    }
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for MockTransport {
+    fn max_msg(&self) -> usize {
+        self.inner.max_msg()
+    }
+
+    fn max_msg_size(&self) -> usize {
+        self.inner.max_msg_size()
+    }
+
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        self.inner.sync_cmds_completed_on_ret()
+    }
+
+    fn chan_available(&self, idx: usize) -> bool {
+        self.inner.chan_available(idx)
+    }
+
+    fn no_completion_irq(&self) -> bool {
+        self.inner.no_completion_irq()
+    }
+
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        self.inner.send_message(xfer)
+    }
+
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        self.inner.poll_done(xfer)
+    }
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        self.inner.fetch_response(xfer)
+    }
+
+    fn completed_token(&mut self) -> Option<u16> {
+        self.inner.completed_token()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec};
+    use nb::block;
+
+    use super::*;
+    use crate::{FuturePoll, Scmi};
+
+    #[test]
+    fn raw_xfer_answers_from_script() {
+        let mock = MockTransport::new();
+        mock.expect(0x80, 0x3, 7u32, 0x1234_5678u32);
+        let scmi = Scmi::new(mock.clone()).unwrap();
+
+        let resp = scmi.raw_xfer(0x80, 0x3, &7u32.to_le_bytes(), 4).unwrap();
+        assert_eq!(resp, 0x1234_5678u32.to_le_bytes());
+        assert_eq!(mock.pending(), 0);
+    }
+
+    #[test]
+    fn error_status_is_reported() {
+        let mock = MockTransport::new();
+        mock.expect_status(0x80, 0x3, (), -4);
+        let scmi = Scmi::new(mock.clone()).unwrap();
+
+        assert_eq!(scmi.raw_xfer(0x80, 0x3, &[], 4), Err(ScmiError::NotFound));
+    }
+
+    #[test]
+    fn boxed_transport() {
+        let mock = MockTransport::new();
+        mock.expect(0x80, 0x3, (), 1u32);
+        let transport: Box<dyn Transport> = Box::new(mock.clone());
+        let scmi = Scmi::new(transport).unwrap();
+
+        assert_eq!(scmi.raw_xfer(0x80, 0x3, &[], 4), Ok(vec![1, 0, 0, 0]));
+        assert!(matches!(scmi.fetch_notification(), Ok(None)));
+    }
+
+    #[test]
+    fn one_transfer_per_channel() {
+        let mock = MockTransport::new();
+        mock.expect(0x80, 0x3, (), 1u32).expect(0x81, 0x3, (), 2u32);
+        let scmi = Scmi::new(mock.clone()).unwrap();
+        let mut first = scmi.open_protocol(0x80);
+        let mut second = scmi.open_protocol(0x81);
+        let mut a = first.raw_xfer(0x3, &[], 4).unwrap();
+        let mut b = second.raw_xfer(0x3, &[], 4).unwrap();
+
+        // The second command would overwrite the answer to the first one.
+        assert!(matches!(a.poll_completion(), Err(nb::Error::WouldBlock)));
+        assert!(matches!(
+            b.poll_completion(),
+            Err(nb::Error::Other(ScmiError::Busy))
+        ));
+        assert_eq!(block!(a.poll_completion()), Ok(vec![1, 0, 0, 0]));
+        assert_eq!(block!(b.poll_completion()), Ok(vec![2, 0, 0, 0]));
+        assert_eq!(mock.pending(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected payload")]
+    fn mismatched_request_panics() {
+        let mock = MockTransport::new();
+        mock.expect(0x80, 0x3, 7u32, ());
+        let scmi = Scmi::new(mock).unwrap();
+
+        let _ = scmi.raw_xfer(0x80, 0x3, &8u32.to_le_bytes(), 0);
+    }
+}
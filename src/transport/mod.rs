@@ -1,19 +1,135 @@
-use crate::{Shmem, err::ScmiError, protocol::Xfer};
+use alloc::boxed::Box;
 
+use crate::{
+    err::ScmiError,
+    protocol::{Notification, Xfer},
+};
+
+mod ffa;
+#[cfg(any(test, feature = "mock"))]
+mod loopback;
+mod mailbox;
+mod mhuv2;
+mod mhuv3;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+mod optee;
 mod smc;
+mod virtio;
 
-pub use smc::Smc;
+pub use ffa::{Ffa, FfaAbi, FfaSmc};
+#[cfg(any(test, feature = "mock"))]
+pub use loopback::{Loopback, Platform};
+pub use mailbox::{Doorbell, Mailbox};
+pub use mhuv2::Mhuv2Doorbell;
+pub use mhuv3::Mhuv3Doorbell;
+#[cfg(any(test, feature = "mock"))]
+pub use mock::MockTransport;
+pub use optee::{Optee, OpteeAbi, OpteeParam, OpteeSmc};
+pub use smc::{Conduit, Smc};
+pub use virtio::Virtio;
 
+/// Channel to the platform.
+///
+/// The trait is object safe, so the transport can be picked at runtime
+/// from the device tree and used as `Scmi<Box<dyn Transport>>`.
 pub trait Transport {
-    const MAX_MSG: usize;
-    const MAX_MSG_SIZE: usize;
-    const SYNC_CMDS_COMPLETED_ON_RET: bool;
+    /// Number of transfers that can be in flight at once, `1` for a single
+    /// shared memory channel that the next command would overwrite.
+    fn max_msg(&self) -> usize;
+
+    /// Largest message payload, status word excluded.
+    fn max_msg_size(&self) -> usize;
+
+    /// Whether synchronous commands are answered by the time
+    /// [`Transport::send_message`] returns.
+    fn sync_cmds_completed_on_ret(&self) -> bool;
 
+    /// Whether channel `idx` exists: `0` carries commands, `1` platform
+    /// notifications and delayed responses.
     fn chan_available(&self, idx: usize) -> bool;
+
+    /// Whether completion must be polled for, the transport having no
+    /// interrupt.
     fn no_completion_irq(&self) -> bool;
     // fn chan_setup(&mut self, info: ChannelInfo);
     // fn chan_free(&mut self, idx: usize);
-    fn send_message(&mut self, shmem: &mut Shmem, xfer: &Xfer) -> Result<(), ScmiError>;
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError>;
+
+    /// Checks whether the platform has answered `xfer`.
+    fn poll_done(&mut self, xfer: &Xfer) -> bool;
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError>;
+
+    /// Hands the channel back once `xfer` is over: its response consumed,
+    /// its sending or fetching failed, or the caller gave up waiting for it.
+    fn mark_txdone(&mut self, _xfer: &Xfer) {}
+
+    /// Acknowledges the completion interrupt, before
+    /// [`Transport::completed_token`] is asked which transfer it was for.
+    fn ack_irq(&mut self) {}
+
+    /// Token of the transfer the completion interrupt was raised for.
+    ///
+    /// `None` when the transport cannot tell, every pending transfer is then
+    /// woken to check for itself.
+    fn completed_token(&mut self) -> Option<u16> {
+        None
+    }
+
+    /// Reads a pending platform initiated message, if the transport has a
+    /// P2A channel and something is waiting on it.
+    fn fetch_notification(&mut self) -> Result<Option<Notification>, ScmiError> {
+        Ok(None)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn max_msg(&self) -> usize {
+        (**self).max_msg()
+    }
+
+    fn max_msg_size(&self) -> usize {
+        (**self).max_msg_size()
+    }
+
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        (**self).sync_cmds_completed_on_ret()
+    }
+
+    fn chan_available(&self, idx: usize) -> bool {
+        (**self).chan_available(idx)
+    }
+
+    fn no_completion_irq(&self) -> bool {
+        (**self).no_completion_irq()
+    }
+
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        (**self).send_message(xfer)
+    }
+
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        (**self).poll_done(xfer)
+    }
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        (**self).fetch_response(xfer)
+    }
+
+    fn mark_txdone(&mut self, xfer: &Xfer) {
+        (**self).mark_txdone(xfer)
+    }
+
+    fn ack_irq(&mut self) {
+        (**self).ack_irq()
+    }
+
+    fn completed_token(&mut self) -> Option<u16> {
+        (**self).completed_token()
+    }
 
-    fn fetch_response(&mut self, shmem: &mut Shmem, xfer: &mut Xfer) -> Result<(), ScmiError>;
+    fn fetch_notification(&mut self) -> Result<Option<Notification>, ScmiError> {
+        (**self).fetch_notification()
+    }
 }
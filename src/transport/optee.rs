@@ -0,0 +1,843 @@
+use core::ptr::NonNull;
+
+use alloc::vec::Vec;
+
+use crate::{Conduit, Shmem, ShmemLayout, Transport, Xfer, err::ScmiError};
+
+/// Parameter of an OP-TEE invocation.
+///
+/// Memory references are copied in and out of the shared memory by the
+/// [`OpteeAbi`] implementation.
+#[derive(Debug)]
+pub enum OpteeParam<'a> {
+    ValueInput {
+        a: u64,
+        b: u64,
+        c: u64,
+    },
+    ValueOutput {
+        a: u64,
+        b: u64,
+        c: u64,
+    },
+    ValueInout {
+        a: u64,
+        b: u64,
+        c: u64,
+    },
+    MemrefInput(&'a [u8]),
+    /// `size` is set to the number of bytes written by the TA.
+    MemrefOutput {
+        buf: &'a mut [u8],
+        size: usize,
+    },
+}
+
+/// Client side of the OP-TEE message protocol.
+///
+/// [`OpteeSmc`] talks to a real OP-TEE through the SMC ABI; tests can plug a
+/// software stand-in instead.
+pub trait OpteeAbi {
+    /// Opens a session on the TA or PTA `uuid` and returns its id.
+    fn open_session(&mut self, uuid: &[u8; 16]) -> Result<u32, ScmiError>;
+
+    /// Invokes `func` in `session`, updating the output parameters.
+    fn invoke(
+        &mut self,
+        session: u32,
+        func: u32,
+        params: &mut [OpteeParam<'_>],
+    ) -> Result<(), ScmiError>;
+
+    fn close_session(&mut self, session: u32);
+}
+
+const OPTEE_SMC_CALL_WITH_ARG: u32 = 0x3200_0004;
+const OPTEE_SMC_CALL_RETURN_FROM_RPC: u32 = 0x3200_0003;
+
+const OPTEE_SMC_RETURN_OK: u32 = 0;
+const OPTEE_SMC_RETURN_ETHREAD_LIMIT: u32 = 1;
+const OPTEE_SMC_RETURN_EBUSY: u32 = 2;
+const OPTEE_SMC_RETURN_RPC_PREFIX_MASK: u32 = 0xFFFF_0000;
+const OPTEE_SMC_RETURN_RPC_PREFIX: u32 = 0xFFFF_0000;
+
+const OPTEE_SMC_RPC_FUNC_ALLOC: u32 = 0;
+const OPTEE_SMC_RPC_FUNC_FOREIGN_INTR: u32 = 4;
+
+const OPTEE_MSG_CMD_OPEN_SESSION: u32 = 0;
+const OPTEE_MSG_CMD_INVOKE_COMMAND: u32 = 1;
+const OPTEE_MSG_CMD_CLOSE_SESSION: u32 = 2;
+
+const OPTEE_MSG_ATTR_TYPE_VALUE_INPUT: u64 = 0x1;
+const OPTEE_MSG_ATTR_TYPE_VALUE_OUTPUT: u64 = 0x2;
+const OPTEE_MSG_ATTR_TYPE_VALUE_INOUT: u64 = 0x3;
+const OPTEE_MSG_ATTR_TYPE_TMEM_INPUT: u64 = 0x9;
+const OPTEE_MSG_ATTR_TYPE_TMEM_OUTPUT: u64 = 0xA;
+const OPTEE_MSG_ATTR_META: u64 = 1 << 8;
+
+const TEEC_LOGIN_PUBLIC: u64 = 0;
+
+const TEEC_ERROR_BAD_PARAMETERS: u32 = 0xFFFF_0006;
+const TEEC_ERROR_ITEM_NOT_FOUND: u32 = 0xFFFF_0008;
+const TEEC_ERROR_NOT_SUPPORTED: u32 = 0xFFFF_000A;
+const TEEC_ERROR_OUT_OF_MEMORY: u32 = 0xFFFF_000C;
+const TEEC_ERROR_BUSY: u32 = 0xFFFF_000D;
+const TEEC_ERROR_SHORT_BUFFER: u32 = 0xFFFF_0010;
+
+/// `struct optee_msg_arg` without its parameters.
+const MSG_ARG_SIZE: usize = 0x20;
+const MSG_PARAM_SIZE: usize = 4 * size_of::<u64>();
+const MAX_PARAMS: usize = 6;
+/// Memory references are copied after the argument block.
+const MEMREF_OFFSET: usize = MSG_ARG_SIZE + MAX_PARAMS * MSG_PARAM_SIZE;
+
+/// [`OpteeAbi`] over the OP-TEE SMC interface.
+///
+/// Messages and memory references go through `shm`, which must lie in the
+/// shared memory OP-TEE accepts for non-registered buffers: its reserved
+/// area, or memory from [`Shmem::alloc`] when OP-TEE supports dynamic shared
+/// memory.
+pub struct OpteeSmc {
+    conduit: Conduit,
+    shm: Shmem,
+}
+
+impl OpteeSmc {
+    pub const COMPATIBLE: &str = "linaro,optee-tz";
+
+    pub fn new(conduit: Conduit, shm: Shmem) -> Self {
+        OpteeSmc { conduit, shm }
+    }
+
+    fn write_u32(&mut self, offset: usize, val: u32) {
+        unsafe {
+            (self.shm.address().as_ptr().add(offset) as *mut u32).write_volatile(val);
+        }
+    }
+
+    fn write_u64(&mut self, offset: usize, val: u64) {
+        unsafe {
+            (self.shm.address().as_ptr().add(offset) as *mut u64).write_volatile(val);
+        }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { (self.shm.address().as_ptr().add(offset) as *const u32).read_volatile() }
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        unsafe { (self.shm.address().as_ptr().add(offset) as *const u64).read_volatile() }
+    }
+
+    fn write_param(&mut self, idx: usize, attr: u64, vals: [u64; 3]) {
+        let offset = MSG_ARG_SIZE + idx * MSG_PARAM_SIZE;
+        self.write_u64(offset, attr);
+        for (i, val) in vals.into_iter().enumerate() {
+            self.write_u64(offset + (i + 1) * size_of::<u64>(), val);
+        }
+    }
+
+    fn read_param(&self, idx: usize) -> [u64; 3] {
+        let offset = MSG_ARG_SIZE + idx * MSG_PARAM_SIZE + size_of::<u64>();
+        [
+            self.read_u64(offset),
+            self.read_u64(offset + size_of::<u64>()),
+            self.read_u64(offset + 2 * size_of::<u64>()),
+        ]
+    }
+
+    /// Fills the argument block, makes the call and checks its result.
+    fn do_call(
+        &mut self,
+        cmd: u32,
+        session: u32,
+        func: u32,
+        meta: &[[u64; 3]],
+        params: &mut [OpteeParam<'_>],
+    ) -> Result<(), ScmiError> {
+        let num_params = meta.len() + params.len();
+        if num_params > MAX_PARAMS {
+            return Err(ScmiError::InvalidParameters);
+        }
+        if self.shm.size() < MEMREF_OFFSET {
+            error!(
+                "OP-TEE shared memory of {:#x} bytes too small",
+                self.shm.size()
+            );
+            return Err(ScmiError::NoMemory);
+        }
+        self.write_u32(0, cmd);
+        self.write_u32(4, func);
+        self.write_u32(8, session);
+        // cancel_id, pad, ret, ret_origin
+        for offset in (12..28).step_by(4) {
+            self.write_u32(offset, 0);
+        }
+        self.write_u32(28, num_params as u32);
+
+        for (i, vals) in meta.iter().enumerate() {
+            self.write_param(
+                i,
+                OPTEE_MSG_ATTR_TYPE_VALUE_INPUT | OPTEE_MSG_ATTR_META,
+                *vals,
+            );
+        }
+        let mut memref = MEMREF_OFFSET;
+        for (i, param) in params.iter().enumerate() {
+            let idx = meta.len() + i;
+            match param {
+                OpteeParam::ValueInput { a, b, c } => {
+                    self.write_param(idx, OPTEE_MSG_ATTR_TYPE_VALUE_INPUT, [*a, *b, *c])
+                }
+                OpteeParam::ValueOutput { .. } => {
+                    self.write_param(idx, OPTEE_MSG_ATTR_TYPE_VALUE_OUTPUT, [0; 3])
+                }
+                OpteeParam::ValueInout { a, b, c } => {
+                    self.write_param(idx, OPTEE_MSG_ATTR_TYPE_VALUE_INOUT, [*a, *b, *c])
+                }
+                OpteeParam::MemrefInput(buf) => {
+                    let addr = self.memref(&mut memref, buf.len())?;
+                    unsafe {
+                        let dest = self.shm.address().as_ptr().add(addr);
+                        for (i, &b) in buf.iter().enumerate() {
+                            dest.add(i).write_volatile(b);
+                        }
+                    }
+                    let pa = (self.shm.bus_address() + addr) as u64;
+                    self.write_param(
+                        idx,
+                        OPTEE_MSG_ATTR_TYPE_TMEM_INPUT,
+                        [pa, buf.len() as u64, 0],
+                    );
+                }
+                OpteeParam::MemrefOutput { buf, .. } => {
+                    let addr = self.memref(&mut memref, buf.len())?;
+                    let pa = (self.shm.bus_address() + addr) as u64;
+                    self.write_param(
+                        idx,
+                        OPTEE_MSG_ATTR_TYPE_TMEM_OUTPUT,
+                        [pa, buf.len() as u64, 0],
+                    );
+                }
+            }
+        }
+
+        self.call_with_arg()?;
+
+        let ret = self.read_u32(20);
+        if ret != 0 {
+            let origin = self.read_u32(24);
+            warn!("OP-TEE command {cmd} func {func} failed: {ret:#x} from {origin}");
+            return Err(teec_error(ret));
+        }
+
+        let mut memref = MEMREF_OFFSET;
+        for (i, param) in params.iter_mut().enumerate() {
+            let vals = self.read_param(meta.len() + i);
+            match param {
+                OpteeParam::ValueOutput { a, b, c } | OpteeParam::ValueInout { a, b, c } => {
+                    [*a, *b, *c] = vals;
+                }
+                OpteeParam::MemrefInput(buf) => {
+                    self.memref(&mut memref, buf.len())?;
+                }
+                OpteeParam::MemrefOutput { buf, size } => {
+                    let addr = self.memref(&mut memref, buf.len())?;
+                    *size = vals[1] as usize;
+                    let len = (*size).min(buf.len());
+                    unsafe {
+                        let src = self.shm.address().as_ptr().add(addr);
+                        for (i, b) in buf[..len].iter_mut().enumerate() {
+                            *b = src.add(i).read_volatile();
+                        }
+                    }
+                }
+                OpteeParam::ValueInput { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Reserves `len` bytes for a memory reference at `*next`.
+    fn memref(&self, next: &mut usize, len: usize) -> Result<usize, ScmiError> {
+        let addr = *next;
+        let end = addr
+            .checked_add(len)
+            .filter(|&end| end <= self.shm.size())
+            .ok_or(ScmiError::NoMemory)?;
+        *next = end.next_multiple_of(size_of::<u64>());
+        Ok(addr)
+    }
+
+    fn call_with_arg(&mut self) -> Result<(), ScmiError> {
+        let pa = self.shm.bus_address() as u64;
+        let mut func = OPTEE_SMC_CALL_WITH_ARG;
+        let mut args = [0; 17];
+        args[0] = pa >> 32;
+        args[1] = pa & 0xFFFF_FFFF;
+        loop {
+            let ret = self.conduit.call64(func, args);
+            let code = ret[0] as u32;
+            if code & OPTEE_SMC_RETURN_RPC_PREFIX_MASK != OPTEE_SMC_RETURN_RPC_PREFIX {
+                return match code {
+                    OPTEE_SMC_RETURN_OK => Ok(()),
+                    OPTEE_SMC_RETURN_ETHREAD_LIMIT | OPTEE_SMC_RETURN_EBUSY => Err(ScmiError::Busy),
+                    _ => {
+                        error!("OP-TEE call failed: {code:#x}");
+                        Err(ScmiError::TransportFailure)
+                    }
+                };
+            }
+
+            // Resume with a1-a3 as returned, OP-TEE keeps its thread in a3.
+            func = OPTEE_SMC_CALL_RETURN_FROM_RPC;
+            args = [0; 17];
+            args[..3].copy_from_slice(&ret[1..4]);
+            match code & !OPTEE_SMC_RETURN_RPC_PREFIX_MASK {
+                // Our interrupt was taken while in OP-TEE and is handled by now.
+                OPTEE_SMC_RPC_FUNC_FOREIGN_INTR => {}
+                OPTEE_SMC_RPC_FUNC_ALLOC => {
+                    debug!("Refusing OP-TEE shared memory allocation");
+                    args[0] = 0;
+                    args[1] = 0;
+                }
+                rpc => debug!("Ignoring OP-TEE RPC {rpc}"),
+            }
+        }
+    }
+}
+
+fn teec_error(ret: u32) -> ScmiError {
+    match ret {
+        TEEC_ERROR_BAD_PARAMETERS => ScmiError::InvalidParameters,
+        TEEC_ERROR_ITEM_NOT_FOUND => ScmiError::NotFound,
+        TEEC_ERROR_NOT_SUPPORTED => ScmiError::NotSupported,
+        TEEC_ERROR_OUT_OF_MEMORY => ScmiError::NoMemory,
+        TEEC_ERROR_BUSY => ScmiError::Busy,
+        TEEC_ERROR_SHORT_BUFFER => ScmiError::ProtocolError,
+        _ => ScmiError::CommunicationError,
+    }
+}
+
+impl OpteeAbi for OpteeSmc {
+    fn open_session(&mut self, uuid: &[u8; 16]) -> Result<u32, ScmiError> {
+        // The UUID octets are passed as is in the first two values.
+        let (mut lo, mut hi) = ([0; 8], [0; 8]);
+        lo.copy_from_slice(&uuid[..8]);
+        hi.copy_from_slice(&uuid[8..]);
+        let ta = [u64::from_ne_bytes(lo), u64::from_ne_bytes(hi), 0];
+        let client = [0, 0, TEEC_LOGIN_PUBLIC];
+        self.do_call(OPTEE_MSG_CMD_OPEN_SESSION, 0, 0, &[ta, client], &mut [])?;
+        Ok(self.read_u32(8))
+    }
+
+    fn invoke(
+        &mut self,
+        session: u32,
+        func: u32,
+        params: &mut [OpteeParam<'_>],
+    ) -> Result<(), ScmiError> {
+        self.do_call(OPTEE_MSG_CMD_INVOKE_COMMAND, session, func, &[], params)
+    }
+
+    fn close_session(&mut self, session: u32) {
+        if let Err(e) = self.do_call(OPTEE_MSG_CMD_CLOSE_SESSION, session, 0, &[], &mut []) {
+            warn!("Failed to close OP-TEE session {session}: {e}");
+        }
+    }
+}
+
+const PTA_SCMI_CMD_CAPABILITIES: u32 = 0;
+const PTA_SCMI_CMD_PROCESS_SMT_CHANNEL: u32 = 1;
+const PTA_SCMI_CMD_GET_CHANNEL: u32 = 3;
+const PTA_SCMI_CMD_PROCESS_MSG_CHANNEL: u32 = 4;
+
+const PTA_SCMI_CAPS_SMT_HEADER: u64 = 1 << 0;
+const PTA_SCMI_CAPS_MSG_HEADER: u64 = 1 << 1;
+
+const MAX_MSG_SIZE: usize = 128;
+
+enum OpteeChannel {
+    /// SMT layout in a shared memory area known to OP-TEE.
+    Smt(Shmem),
+    /// MSG layout buffers passed as memory references, in words to keep
+    /// them aligned for [`Shmem`].
+    Msg {
+        tx: Vec<u32>,
+        rx: Vec<u32>,
+        rx_len: usize,
+    },
+}
+
+/// A MSG layout view of a heap buffer.
+fn msg_view(buf: &mut [u32]) -> Result<Shmem, ScmiError> {
+    let size = size_of_val(buf);
+    unsafe { Shmem::new(NonNull::from(buf).cast(), 0, size, ShmemLayout::Msg) }
+}
+
+fn msg_buf() -> Result<Vec<u32>, ScmiError> {
+    let len = (2 * size_of::<u32>() + MAX_MSG_SIZE).div_ceil(size_of::<u32>());
+    let mut buf = Vec::new();
+    buf.try_reserve_exact(len)
+        .map_err(|_| ScmiError::NoMemory)?;
+    buf.resize(len, 0);
+    Ok(buf)
+}
+
+fn as_bytes(buf: &[u32]) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(buf.as_ptr().cast(), size_of_val(buf)) }
+}
+
+fn as_bytes_mut(buf: &mut [u32]) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), size_of_val(buf)) }
+}
+
+/// OP-TEE transport (`linaro,scmi-optee`), served by the SCMI PTA.
+///
+/// With a [`Shmem`] the channel uses the SMT layout; otherwise messages are
+/// exchanged as memory references through the [`OpteeAbi`].
+pub struct Optee<A: OpteeAbi> {
+    abi: A,
+    session: u32,
+    channel_id: u32,
+    channel: OpteeChannel,
+}
+
+impl<A: OpteeAbi> Optee<A> {
+    pub const COMPATIBLE: &str = "linaro,scmi-optee";
+    /// Property of the channel node holding the PTA channel id.
+    pub const CHANNEL_ID_PROP: &str = "linaro,optee-channel-id";
+    /// `a8cfe406-d4f5-4a2e-9f8d-a25dc754c099`
+    pub const PTA_SCMI_UUID: [u8; 16] = [
+        0xa8, 0xcf, 0xe4, 0x06, 0xd4, 0xf5, 0x4a, 0x2e, 0x9f, 0x8d, 0xa2, 0x5d, 0xc7, 0x54, 0xc0,
+        0x99,
+    ];
+
+    /// Opens the channel of a node compatible with `compatible`, or fails
+    /// with [`ScmiError::NotSupported`] without calling into OP-TEE.
+    pub fn from_compatible(
+        compatible: &str,
+        abi: A,
+        channel_id: u32,
+        shmem: Option<Shmem>,
+    ) -> Result<Self, ScmiError> {
+        if compatible != Self::COMPATIBLE {
+            return Err(ScmiError::NotSupported);
+        }
+        Self::new(abi, channel_id, shmem)
+    }
+
+    /// Opens the SCMI PTA and the channel `channel_id` on it.
+    pub fn new(mut abi: A, channel_id: u32, shmem: Option<Shmem>) -> Result<Self, ScmiError> {
+        let smt = shmem.is_some();
+        let channel = match shmem {
+            Some(mut shmem) => {
+                shmem.reset();
+                OpteeChannel::Smt(shmem)
+            }
+            None => OpteeChannel::Msg {
+                tx: msg_buf()?,
+                rx: msg_buf()?,
+                rx_len: 0,
+            },
+        };
+
+        let session = abi.open_session(&Self::PTA_SCMI_UUID)?;
+        match Self::get_channel(&mut abi, session, channel_id, smt) {
+            Ok(channel_id) => Ok(Optee {
+                abi,
+                session,
+                channel_id,
+                channel,
+            }),
+            Err(e) => {
+                abi.close_session(session);
+                Err(e)
+            }
+        }
+    }
+
+    fn get_channel(
+        abi: &mut A,
+        session: u32,
+        channel_id: u32,
+        smt: bool,
+    ) -> Result<u32, ScmiError> {
+        let mut params = [OpteeParam::ValueOutput { a: 0, b: 0, c: 0 }];
+        abi.invoke(session, PTA_SCMI_CMD_CAPABILITIES, &mut params)?;
+        let [OpteeParam::ValueOutput { a: caps, .. }] = params else {
+            error!("SCMI PTA answered its capabilities with {params:?}");
+            return Err(ScmiError::ProtocolError);
+        };
+        let wanted = if smt {
+            PTA_SCMI_CAPS_SMT_HEADER
+        } else {
+            PTA_SCMI_CAPS_MSG_HEADER
+        };
+        if caps & wanted == 0 {
+            error!("SCMI PTA does not support the requested channel format, caps {caps:#x}");
+            return Err(ScmiError::NotSupported);
+        }
+
+        let mut params = [OpteeParam::ValueInout {
+            a: channel_id as u64,
+            b: wanted,
+            c: 0,
+        }];
+        abi.invoke(session, PTA_SCMI_CMD_GET_CHANNEL, &mut params)?;
+        let [OpteeParam::ValueInout { a: handle, .. }] = params else {
+            error!("SCMI PTA answered channel {channel_id} with {params:?}");
+            return Err(ScmiError::ProtocolError);
+        };
+        Ok(handle as u32)
+    }
+}
+
+impl<A: OpteeAbi> Transport for Optee<A> {
+    fn max_msg(&self) -> usize {
+        1
+    }
+
+    fn max_msg_size(&self) -> usize {
+        MAX_MSG_SIZE
+    }
+
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        true
+    }
+
+    fn chan_available(&self, idx: usize) -> bool {
+        idx == 0
+    }
+
+    fn no_completion_irq(&self) -> bool {
+        true
+    }
+
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        let channel = OpteeParam::ValueInput {
+            a: self.channel_id as u64,
+            b: 0,
+            c: 0,
+        };
+        trace!("Sending OP-TEE message {:?}", xfer.hdr);
+        match &mut self.channel {
+            OpteeChannel::Smt(shmem) => {
+                shmem.tx_prepare(xfer)?;
+                self.abi.invoke(
+                    self.session,
+                    PTA_SCMI_CMD_PROCESS_SMT_CHANNEL,
+                    &mut [channel],
+                )
+            }
+            OpteeChannel::Msg { tx, rx, rx_len } => {
+                if xfer.tx.len() > MAX_MSG_SIZE {
+                    return Err(ScmiError::InvalidLength(xfer.tx.len()));
+                }
+                let len = msg_view(tx)?.tx_prepare(xfer)?;
+
+                let mut params = [
+                    channel,
+                    OpteeParam::MemrefInput(&as_bytes(tx)[..len]),
+                    OpteeParam::MemrefOutput {
+                        buf: as_bytes_mut(rx),
+                        size: 0,
+                    },
+                ];
+                self.abi
+                    .invoke(self.session, PTA_SCMI_CMD_PROCESS_MSG_CHANNEL, &mut params)?;
+                let [_, _, OpteeParam::MemrefOutput { size, .. }] = params else {
+                    error!("SCMI PTA answered a message with {params:?}");
+                    return Err(ScmiError::ProtocolError);
+                };
+                *rx_len = size;
+                Ok(())
+            }
+        }
+    }
+
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        match &mut self.channel {
+            OpteeChannel::Smt(shmem) => shmem.poll_done(xfer),
+            OpteeChannel::Msg { .. } => true,
+        }
+    }
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        match &mut self.channel {
+            OpteeChannel::Smt(shmem) => shmem.fetch_response(xfer, MAX_MSG_SIZE),
+            OpteeChannel::Msg { rx, rx_len, .. } => {
+                msg_view(rx)?.fetch_msg_response(xfer, *rx_len, MAX_MSG_SIZE)
+            }
+        }
+    }
+}
+
+impl<A: OpteeAbi> Drop for Optee<A> {
+    fn drop(&mut self) {
+        self.abi.close_session(self.session);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+    use core::cell::Cell;
+    use tock_registers::interfaces::{Readable, Writeable};
+
+    use super::*;
+    use crate::{
+        MsgHeader, Platform, Scmi,
+        emulator::{EmulatedClock, Emulator},
+    };
+
+    const SESSION: u32 = 5;
+    const CHANNEL: u32 = 2;
+    /// Added to the channel id to make the handle returned by GET_CHANNEL.
+    const HANDLE_BASE: u64 = 0x100;
+    const SHMEM_SIZE: usize = 0x100;
+
+    /// SCMI PTA answering from an [`Emulator`].
+    ///
+    /// SMT channels are read and written through a second [`Shmem`] view of
+    /// the agent's channel, as OP-TEE would from its own mapping.
+    struct FakePta {
+        emulator: Emulator,
+        caps: u64,
+        smt: Option<Shmem>,
+        /// Open sessions, shared with the test.
+        sessions: Rc<Cell<u32>>,
+        /// Command whose last parameter comes back as another variant.
+        mangle: Option<u32>,
+    }
+
+    impl FakePta {
+        fn new(caps: u64, smt: Option<Shmem>) -> Self {
+            FakePta {
+                emulator: Emulator {
+                    clocks: vec![EmulatedClock::new("cpu", 1_200_000_000)],
+                    ..Default::default()
+                },
+                caps,
+                smt,
+                sessions: Rc::new(Cell::new(0)),
+                mangle: None,
+            }
+        }
+
+        /// Handles a command, returns the status word and payload.
+        fn answer(&mut self, hdr: u32, req: &[u8]) -> Vec<u8> {
+            let hdr = MsgHeader::unpack(hdr);
+            let (status, resp) = match self.emulator.handle(hdr.protocol_id, hdr.id, req) {
+                Ok(resp) => (ScmiError::SUCCESS, resp),
+                Err(status) => (status, Vec::new()),
+            };
+            let mut answer = Vec::new();
+            answer.extend_from_slice(&status.to_le_bytes());
+            answer.extend_from_slice(&resp);
+            answer
+        }
+
+        fn check_channel(&self, param: &OpteeParam<'_>) -> Result<(), ScmiError> {
+            match param {
+                OpteeParam::ValueInput { a, .. } if *a == CHANNEL as u64 + HANDLE_BASE => Ok(()),
+                _ => Err(ScmiError::InvalidParameters),
+            }
+        }
+
+        fn process_smt(&mut self) {
+            let smt = self.smt.as_mut().expect("no SMT channel");
+            let hdr = smt.header().msg_header.get();
+            let len = smt.header().length.get() as usize - size_of::<u32>();
+            let mut req = vec![0; len];
+            smt.read_payload(&mut req, 0).unwrap();
+
+            let answer = self.answer(hdr, &req);
+            let smt = self.smt.as_mut().unwrap();
+            smt.write_payload(&answer).unwrap();
+            smt.header()
+                .length
+                .set((size_of::<u32>() + answer.len()) as u32);
+            smt.clear_channel();
+        }
+    }
+
+    impl OpteeAbi for FakePta {
+        fn open_session(&mut self, uuid: &[u8; 16]) -> Result<u32, ScmiError> {
+            assert_eq!(*uuid, Optee::<Self>::PTA_SCMI_UUID);
+            self.sessions.set(self.sessions.get() + 1);
+            Ok(SESSION)
+        }
+
+        fn invoke(
+            &mut self,
+            session: u32,
+            func: u32,
+            params: &mut [OpteeParam<'_>],
+        ) -> Result<(), ScmiError> {
+            assert_eq!(session, SESSION);
+            self.command(func, params)?;
+            if self.mangle == Some(func) {
+                *params.last_mut().unwrap() = OpteeParam::ValueInput { a: 0, b: 0, c: 0 };
+            }
+            Ok(())
+        }
+
+        fn close_session(&mut self, session: u32) {
+            assert_eq!(session, SESSION);
+            self.sessions.set(self.sessions.get() - 1);
+        }
+    }
+
+    impl FakePta {
+        fn command(&mut self, func: u32, params: &mut [OpteeParam<'_>]) -> Result<(), ScmiError> {
+            match (func, params) {
+                (PTA_SCMI_CMD_CAPABILITIES, [OpteeParam::ValueOutput { a, .. }]) => {
+                    *a = self.caps;
+                    Ok(())
+                }
+                (PTA_SCMI_CMD_GET_CHANNEL, [OpteeParam::ValueInout { a, b, .. }]) => {
+                    if *a != CHANNEL as u64 {
+                        return Err(ScmiError::NotFound);
+                    }
+                    if *b & self.caps == 0 {
+                        return Err(ScmiError::NotSupported);
+                    }
+                    *a += HANDLE_BASE;
+                    Ok(())
+                }
+                (PTA_SCMI_CMD_PROCESS_SMT_CHANNEL, [channel]) => {
+                    self.check_channel(channel)?;
+                    self.process_smt();
+                    Ok(())
+                }
+                (
+                    PTA_SCMI_CMD_PROCESS_MSG_CHANNEL,
+                    [
+                        channel,
+                        OpteeParam::MemrefInput(req),
+                        OpteeParam::MemrefOutput { buf, size },
+                    ],
+                ) => {
+                    self.check_channel(channel)?;
+                    let hdr = u32::from_le_bytes(req[..4].try_into().unwrap());
+                    let answer = self.answer(hdr, &req[4..]);
+                    buf[..4].copy_from_slice(&hdr.to_le_bytes());
+                    buf[4..4 + answer.len()].copy_from_slice(&answer);
+                    *size = 4 + answer.len();
+                    Ok(())
+                }
+                (func, params) => panic!("unexpected PTA command {func} with {params:?}"),
+            }
+        }
+    }
+
+    /// Two views of one heap SMT channel: the agent's and the PTA's.
+    fn smt_channel(mem: &mut [u32]) -> (Shmem, Shmem) {
+        let address = NonNull::from(mem).cast();
+        let view = || unsafe {
+            Shmem::new(
+                address,
+                address.as_ptr() as usize,
+                SHMEM_SIZE,
+                ShmemLayout::Smt,
+            )
+            .unwrap()
+        };
+        (view(), view())
+    }
+
+    #[test]
+    fn smt_channel_layout() {
+        let mut mem = vec![0u32; SHMEM_SIZE / 4].into_boxed_slice();
+        let (agent, platform) = smt_channel(&mut mem);
+        let pta = FakePta::new(PTA_SCMI_CAPS_SMT_HEADER, Some(platform));
+        let sessions = pta.sessions.clone();
+
+        let scmi = Scmi::new(Optee::new(pta, CHANNEL, Some(agent)).unwrap()).unwrap();
+        let mut clk = scmi.protocol_clk().unwrap();
+        assert_eq!(clk.num_clocks(), 1);
+        assert_eq!(clk.rate_get(0), Ok(1_200_000_000));
+        assert_eq!(clk.rate_get(1), Err(ScmiError::NotFound));
+        assert_eq!(sessions.get(), 1);
+
+        drop(clk);
+        drop(scmi);
+        assert_eq!(sessions.get(), 0, "session closed on drop");
+    }
+
+    #[test]
+    fn msg_channel_layout() {
+        let pta = FakePta::new(PTA_SCMI_CAPS_MSG_HEADER, None);
+        let scmi = Scmi::new(Optee::new(pta, CHANNEL, None).unwrap()).unwrap();
+        let mut clk = scmi.protocol_clk().unwrap();
+        assert_eq!(clk.rate_get(0), Ok(1_200_000_000));
+        clk.rate_set(0, 800_000_000).unwrap();
+        assert_eq!(clk.rate_get(0), Ok(800_000_000));
+        assert_eq!(clk.rate_get(1), Err(ScmiError::NotFound));
+    }
+
+    #[test]
+    fn msg_buffers_are_aligned() {
+        let mut buf = msg_buf().unwrap();
+        assert!(size_of_val(&buf[..]) >= 2 * size_of::<u32>() + MAX_MSG_SIZE);
+        msg_view(&mut buf).unwrap();
+    }
+
+    #[test]
+    fn unsupported_layout_closes_the_session() {
+        let pta = FakePta::new(PTA_SCMI_CAPS_SMT_HEADER, None);
+        let sessions = pta.sessions.clone();
+        assert_eq!(
+            Optee::new(pta, CHANNEL, None).err(),
+            Some(ScmiError::NotSupported)
+        );
+        assert_eq!(sessions.get(), 0);
+    }
+
+    #[test]
+    fn unexpected_param_variant() {
+        for func in [PTA_SCMI_CMD_CAPABILITIES, PTA_SCMI_CMD_GET_CHANNEL] {
+            let mut pta = FakePta::new(PTA_SCMI_CAPS_MSG_HEADER, None);
+            pta.mangle = Some(func);
+            let sessions = pta.sessions.clone();
+            assert_eq!(
+                Optee::new(pta, CHANNEL, None).err(),
+                Some(ScmiError::ProtocolError)
+            );
+            assert_eq!(sessions.get(), 0);
+        }
+
+        let mut optee =
+            Optee::new(FakePta::new(PTA_SCMI_CAPS_MSG_HEADER, None), CHANNEL, None).unwrap();
+        optee.abi.mangle = Some(PTA_SCMI_CMD_PROCESS_MSG_CHANNEL);
+        let mut xfer = Xfer::new(0x6, MAX_MSG_SIZE).unwrap();
+        xfer.hdr.protocol_id = 0x14;
+        xfer.tx.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(optee.send_message(&xfer), Err(ScmiError::ProtocolError));
+    }
+
+    #[test]
+    fn unknown_channel() {
+        let pta = FakePta::new(PTA_SCMI_CAPS_MSG_HEADER, None);
+        let sessions = pta.sessions.clone();
+        assert_eq!(Optee::new(pta, 7, None).err(), Some(ScmiError::NotFound));
+        assert_eq!(sessions.get(), 0);
+    }
+
+    #[test]
+    fn boxed_from_compatible() {
+        let pta = FakePta::new(PTA_SCMI_CAPS_MSG_HEADER, None);
+        let sessions = pta.sessions.clone();
+        let res = Optee::from_compatible("arm,scmi-smc", pta, CHANNEL, None);
+        assert_eq!(res.err(), Some(ScmiError::NotSupported));
+        assert_eq!(sessions.get(), 0, "OP-TEE left alone");
+
+        let pta = FakePta::new(PTA_SCMI_CAPS_MSG_HEADER, None);
+        let optee = Optee::from_compatible(Optee::<FakePta>::COMPATIBLE, pta, CHANNEL, None);
+        let transport: Box<dyn Transport> = Box::new(optee.unwrap());
+        let scmi = Scmi::new(transport).unwrap();
+        let mut clk = scmi.protocol_clk().unwrap();
+        assert_eq!(clk.rate_get(0), Ok(1_200_000_000));
+    }
+}
@@ -1,67 +1,176 @@
-use smccc::{error::success_or_error_64, smc64};
-use tock_registers::interfaces::Readable;
+use smccc::error::success_or_error_64;
+#[cfg(target_arch = "aarch64")]
+use smccc::{hvc64, smc64};
 
-use crate::{Shmem, Transport, Xfer, err::ScmiError};
+use crate::{
+    Shmem, Transport, Xfer,
+    err::{ScmiError, SmcccError},
+};
 
+/// Instruction used to trap into the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Conduit {
+    #[default]
+    Smc,
+    /// Used when running as a guest, the hypervisor then provides the
+    /// SCMI channel.
+    Hvc,
+}
+
+impl Conduit {
+    /// Parses the `method` property of the PSCI node.
+    pub fn from_method(method: &str) -> Option<Self> {
+        match method {
+            "smc" => Some(Conduit::Smc),
+            "hvc" => Some(Conduit::Hvc),
+            _ => None,
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub(crate) fn call64(self, func_id: u32, args: [u64; 17]) -> [u64; 18] {
+        match self {
+            Conduit::Smc => smc64(func_id, args),
+            Conduit::Hvc => hvc64(func_id, args),
+        }
+    }
+
+    /// There is no firmware to call on other architectures, as when unit
+    /// testing on the host: every call reports `NOT_SUPPORTED`.
+    #[cfg(not(target_arch = "aarch64"))]
+    pub(crate) fn call64(self, _func_id: u32, _args: [u64; 17]) -> [u64; 18] {
+        let mut ret = [0; 18];
+        ret[0] = SmcccError::NOT_SUPPORTED as u64;
+        ret
+    }
+}
+
+const MAX_MSG_SIZE: usize = 128;
+
+const SHMEM_SHIFT: usize = 12;
+const SHMEM_OFFSET_MASK: usize = (1 << SHMEM_SHIFT) - 1;
+
+/// SMC/HVC doorbell transport over an SMT shared memory channel.
+///
+/// With an `a2p` interrupt the call only rings the doorbell: the platform
+/// answers asynchronously and raises the interrupt, which must be routed to
+/// [`Scmi::handle_irq`](crate::Scmi::handle_irq). Without it the answer is
+/// available as soon as the call returns.
 pub struct Smc {
+    shmem: Shmem,
     func_id: u32,
     irq: Option<u32>,
+    conduit: Conduit,
+    shmem_param: bool,
 }
 
 impl Smc {
-    pub fn new(func_id: u32, irq: Option<u32>) -> Self {
-        Smc { func_id, irq }
+    pub const COMPATIBLE: &str = "arm,scmi-smc";
+    /// Variant passing the shared memory page and offset in the call
+    /// arguments, so that one function id can serve several channels.
+    pub const COMPATIBLE_PARAM: &str = "arm,scmi-smc-param";
+    /// `interrupt-names` entry of the completion interrupt.
+    pub const A2P_IRQ_NAME: &str = "a2p";
+
+    pub fn new(mut shmem: Shmem, func_id: u32, irq: Option<u32>) -> Self {
+        shmem.reset();
+        Smc {
+            shmem,
+            func_id,
+            irq,
+            conduit: Conduit::Smc,
+            shmem_param: false,
+        }
+    }
+
+    /// Builds the transport matching a device tree `compatible` string, or
+    /// fails with [`ScmiError::NotSupported`] for any other.
+    pub fn from_compatible(
+        compatible: &str,
+        shmem: Shmem,
+        func_id: u32,
+        irq: Option<u32>,
+    ) -> Result<Self, ScmiError> {
+        match compatible {
+            Self::COMPATIBLE => Ok(Self::new(shmem, func_id, irq)),
+            Self::COMPATIBLE_PARAM => Ok(Self::new(shmem, func_id, irq).with_shmem_param()),
+            _ => Err(ScmiError::NotSupported),
+        }
+    }
+
+    /// Rings the doorbell with `conduit` instead of an SMC.
+    pub fn with_conduit(mut self, conduit: Conduit) -> Self {
+        self.conduit = conduit;
+        self
+    }
+
+    /// Passes the shared memory page and offset in the first two call
+    /// arguments (`arm,scmi-smc-param`).
+    pub fn with_shmem_param(mut self) -> Self {
+        self.shmem_param = true;
+        self
     }
 
-    fn call(&self) -> Result<(), smccc::psci::Error> {
-        success_or_error_64(smc64(self.func_id, [0; 17])[0])
+    /// The `a2p` completion interrupt, if any.
+    pub fn irq(&self) -> Option<u32> {
+        self.irq
+    }
+
+    fn call(&self) -> Result<(), SmcccError> {
+        let mut args = [0; 17];
+        if self.shmem_param {
+            args[0] = (self.shmem.bus_address() >> SHMEM_SHIFT) as u64;
+            args[1] = (self.shmem.bus_address() & SHMEM_OFFSET_MASK) as u64;
+        }
+        success_or_error_64(self.conduit.call64(self.func_id, args)[0])
     }
 }
 
 impl Transport for Smc {
-    fn chan_available(&self, _idx: usize) -> bool {
-        true
+    fn chan_available(&self, idx: usize) -> bool {
+        idx == 0
     }
 
     fn no_completion_irq(&self) -> bool {
         self.irq.is_none()
     }
 
-    fn send_message(&mut self, shmem: &mut Shmem, xfer: &Xfer) -> Result<(), ScmiError> {
-        shmem.tx_prepare(xfer);
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        self.shmem.tx_prepare(xfer)?;
         trace!("Sending SMC message {:?}", xfer.hdr);
-        self.call().unwrap();
+        self.call().inspect_err(|e| {
+            error!(
+                "{:?} {:#x} failed: {e} ({})",
+                self.conduit,
+                self.func_id,
+                e.code()
+            );
+        })?;
 
         Ok(())
     }
 
-    const MAX_MSG: usize = 20;
+    fn max_msg(&self) -> usize {
+        1
+    }
 
-    const MAX_MSG_SIZE: usize = 128;
+    fn max_msg_size(&self) -> usize {
+        MAX_MSG_SIZE
+    }
 
-    const SYNC_CMDS_COMPLETED_ON_RET: bool = true;
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        true
+    }
 
-    fn fetch_response(&mut self, shmem: &mut Shmem, xfer: &mut Xfer) -> Result<(), ScmiError> {
-        let len = shmem.header().length.get() as usize;
-        let rx_len = len.saturating_sub(8);
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        self.shmem.poll_done(xfer)
+    }
 
-        xfer.hdr.status = unsafe { (shmem.payload_ptr() as *const u32).read_volatile() };
-        trace!(
-            "Fetched SMC response rx_len = {rx_len}, header: {:?}",
-            xfer.hdr
-        );
-        xfer.hdr.to_result()?;
-        xfer.rx.resize(rx_len, 0);
-        if rx_len > 0 {
-            shmem.read_payload(&mut xfer.rx, 4);
-        }
-        trace!(
-            "Fetched response: hdr={:?}, rx_len={}, buff={:?}",
-            xfer.hdr,
-            xfer.rx.len(),
-            xfer.rx
-        );
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        self.shmem.fetch_response(xfer, MAX_MSG_SIZE)
+    }
 
-        Ok(())
+    fn completed_token(&mut self) -> Option<u16> {
+        Some(self.shmem.token())
     }
 }
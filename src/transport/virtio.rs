@@ -0,0 +1,921 @@
+use core::ptr::{NonNull, addr_of_mut};
+
+use alloc::collections::BTreeMap;
+use mbarrier::{rmb, wmb};
+use tock_registers::{interfaces::*, registers::*};
+
+use crate::{Shmem, Transport, Xfer, err::ScmiError, protocol::Notification};
+
+const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+const VIRTIO_MMIO_VERSION: u32 = 2;
+
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+const VIRTIO_SCMI_F_P2A_CHANNELS: u64 = 1 << 0;
+
+const CMDQ: u32 = 0;
+const EVENTQ: u32 = 1;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// Descriptors per virtqueue, every command takes two of them.
+const QUEUE_SIZE: usize = 32;
+const CMD_SLOTS: usize = QUEUE_SIZE / 2;
+const EVENT_BUFS: usize = 16;
+
+const MAX_MSG_SIZE: usize = 128;
+/// Reads of the status register before giving up on a device reset.
+const RESET_POLLS: usize = 1_000_000;
+/// Message header, status word and payload.
+const MSG_BUF_SIZE: usize = 2 * size_of::<u32>() + MAX_MSG_SIZE;
+
+// Split virtqueue layout inside its area of the shared region.
+const DESC_OFFSET: usize = 0;
+const AVAIL_OFFSET: usize = DESC_OFFSET + QUEUE_SIZE * size_of::<Descriptor>();
+const USED_OFFSET: usize = (AVAIL_OFFSET + (3 + QUEUE_SIZE) * size_of::<u16>()).next_multiple_of(4);
+const QUEUE_AREA: usize = 0x800;
+
+const CMDQ_OFFSET: usize = 0;
+const EVENTQ_OFFSET: usize = QUEUE_AREA;
+const BUF_OFFSET: usize = 2 * QUEUE_AREA;
+const EVENT_BUF_OFFSET: usize = BUF_OFFSET + 2 * CMD_SLOTS * MSG_BUF_SIZE;
+
+tock_registers::register_structs! {
+    pub VirtioMmioRegs {
+        (0x000 => magic: ReadOnly<u32>),
+        (0x004 => version: ReadOnly<u32>),
+        (0x008 => device_id: ReadOnly<u32>),
+        (0x00C => vendor_id: ReadOnly<u32>),
+        (0x010 => device_features: ReadOnly<u32>),
+        (0x014 => device_features_sel: WriteOnly<u32>),
+        (0x018 => reserved0: [u32; 2]),
+        (0x020 => driver_features: WriteOnly<u32>),
+        (0x024 => driver_features_sel: WriteOnly<u32>),
+        (0x028 => reserved1: [u32; 2]),
+        (0x030 => queue_sel: WriteOnly<u32>),
+        (0x034 => queue_num_max: ReadOnly<u32>),
+        (0x038 => queue_num: WriteOnly<u32>),
+        (0x03C => reserved2: [u32; 2]),
+        (0x044 => queue_ready: ReadWrite<u32>),
+        (0x048 => reserved3: [u32; 2]),
+        (0x050 => queue_notify: WriteOnly<u32>),
+        (0x054 => reserved4: [u32; 3]),
+        (0x060 => interrupt_status: ReadOnly<u32>),
+        (0x064 => interrupt_ack: WriteOnly<u32>),
+        (0x068 => reserved5: [u32; 2]),
+        (0x070 => status: ReadWrite<u32, DeviceStatus::Register>),
+        (0x074 => reserved6: [u32; 3]),
+        (0x080 => queue_desc_low: WriteOnly<u32>),
+        (0x084 => queue_desc_high: WriteOnly<u32>),
+        (0x088 => reserved7: [u32; 2]),
+        (0x090 => queue_driver_low: WriteOnly<u32>),
+        (0x094 => queue_driver_high: WriteOnly<u32>),
+        (0x098 => reserved8: [u32; 2]),
+        (0x0A0 => queue_device_low: WriteOnly<u32>),
+        (0x0A4 => queue_device_high: WriteOnly<u32>),
+        (0x0A8 => reserved9: [u32; 21]),
+        (0x0FC => config_generation: ReadOnly<u32>),
+        (0x100 => @END),
+    }
+}
+
+tock_registers::register_bitfields![
+    u32,
+    DeviceStatus [
+        ACKNOWLEDGE OFFSET(0) NUMBITS(1) [],
+        DRIVER OFFSET(1) NUMBITS(1) [],
+        DRIVER_OK OFFSET(2) NUMBITS(1) [],
+        FEATURES_OK OFFSET(3) NUMBITS(1) [],
+        DEVICE_NEEDS_RESET OFFSET(6) NUMBITS(1) [],
+        FAILED OFFSET(7) NUMBITS(1) [],
+    ],
+];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// Driver side of a split virtqueue living in the shared region.
+struct Virtqueue {
+    size: u16,
+    base: *mut u8,
+    avail_idx: u16,
+    last_used: u16,
+}
+
+impl Virtqueue {
+    fn set_desc(&mut self, i: u16, desc: Descriptor) {
+        unsafe { self.desc(i).write_volatile(desc) }
+    }
+
+    fn set_desc_len(&mut self, i: u16, len: u32) {
+        unsafe { addr_of_mut!((*self.desc(i)).len).write_volatile(len) }
+    }
+
+    fn desc(&self, i: u16) -> *mut Descriptor {
+        unsafe {
+            self.base
+                .add(DESC_OFFSET)
+                .cast::<Descriptor>()
+                .add(i as usize)
+        }
+    }
+
+    /// Makes the chain starting at `head` available to the device.
+    fn push(&mut self, head: u16) {
+        unsafe {
+            let avail = self.base.add(AVAIL_OFFSET).cast::<u16>();
+            let slot = (self.avail_idx % self.size) as usize;
+            avail.add(2 + slot).write_volatile(head);
+            wmb();
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            avail.add(1).write_volatile(self.avail_idx);
+        }
+        wmb();
+    }
+
+    /// Takes the next chain the device is done with.
+    fn pop_used(&mut self) -> Option<UsedElem> {
+        unsafe {
+            let used = self.base.add(USED_OFFSET);
+            let idx = used.add(size_of::<u16>()).cast::<u16>().read_volatile();
+            if idx == self.last_used {
+                return None;
+            }
+            rmb();
+            let slot = (self.last_used % self.size) as usize;
+            let elem = used
+                .add(2 * size_of::<u16>())
+                .cast::<UsedElem>()
+                .add(slot)
+                .read_volatile();
+            self.last_used = self.last_used.wrapping_add(1);
+            Some(elem)
+        }
+    }
+}
+
+/// virtio-scmi transport over virtio-mmio.
+///
+/// Commands travel on the cmdq, notifications and delayed responses on the
+/// eventq when the device offers `VIRTIO_SCMI_F_P2A_CHANNELS`. Virtqueues and
+/// message buffers are carved out of a [`Shmem`] region of at least
+/// [`Virtio::SHMEM_SIZE`] bytes the device can reach at its `bus_address`.
+pub struct Virtio {
+    regs: NonNull<VirtioMmioRegs>,
+    shmem: Shmem,
+    irq: Option<u32>,
+    cmdq: Virtqueue,
+    eventq: Option<Virtqueue>,
+    slots: [Slot; CMD_SLOTS],
+    /// Answered commands by token, with their slot and response length.
+    done: BTreeMap<u16, (usize, usize)>,
+}
+
+impl Virtio {
+    /// Compatible of the SCMI node.
+    pub const COMPATIBLE: &str = "arm,scmi-virtio";
+    /// Compatible of the virtio-mmio device node.
+    pub const MMIO_COMPATIBLE: &str = "virtio,mmio";
+    pub const DEVICE_ID: u32 = 32;
+    /// Size of the region needed for the virtqueues and their buffers.
+    pub const SHMEM_SIZE: usize = EVENT_BUF_OFFSET + EVENT_BUFS * MSG_BUF_SIZE;
+
+    /// [`Virtio::new`] for an SCMI node compatible with `compatible`, or
+    /// fails with [`ScmiError::NotSupported`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Virtio::new`].
+    pub unsafe fn from_compatible(
+        compatible: &str,
+        mmio: NonNull<u8>,
+        shmem: Shmem,
+        irq: Option<u32>,
+    ) -> Result<Self, ScmiError> {
+        if compatible != Self::COMPATIBLE {
+            return Err(ScmiError::NotSupported);
+        }
+        unsafe { Self::new(mmio, shmem, irq) }
+    }
+
+    /// Probes and brings up a virtio-scmi device.
+    ///
+    /// # Safety
+    ///
+    /// `mmio` must be the mapped base of a virtio-mmio device, and `shmem`,
+    /// usually from [`Shmem::alloc`], must be memory the device can access.
+    pub unsafe fn new(
+        mmio: NonNull<u8>,
+        shmem: Shmem,
+        irq: Option<u32>,
+    ) -> Result<Self, ScmiError> {
+        if shmem.size() < Self::SHMEM_SIZE {
+            error!(
+                "virtio-scmi needs {:#x} bytes of shared memory, got {:#x}",
+                Self::SHMEM_SIZE,
+                shmem.size()
+            );
+            return Err(ScmiError::InvalidParameters);
+        }
+        let regs = mmio.cast::<VirtioMmioRegs>();
+        let r = unsafe { regs.as_ref() };
+
+        let magic = r.magic.get();
+        let version = r.version.get();
+        let device_id = r.device_id.get();
+        if magic != VIRTIO_MMIO_MAGIC
+            || version != VIRTIO_MMIO_VERSION
+            || device_id != Self::DEVICE_ID
+        {
+            error!(
+                "Not a virtio-scmi device: magic {magic:#x}, version {version}, device {device_id}"
+            );
+            return Err(ScmiError::NotSupported);
+        }
+
+        let cmdq = Virtqueue {
+            size: 0,
+            base: unsafe { shmem.address().as_ptr().add(CMDQ_OFFSET) },
+            avail_idx: 0,
+            last_used: 0,
+        };
+        let mut virtio = Virtio {
+            regs,
+            shmem,
+            irq,
+            cmdq,
+            eventq: None,
+            slots: [Slot::Free; CMD_SLOTS],
+            done: BTreeMap::new(),
+        };
+        virtio.init().inspect_err(|_| {
+            virtio.regs().status.modify(DeviceStatus::FAILED::SET);
+        })?;
+        Ok(virtio)
+    }
+
+    /// The virtio-mmio interrupt, if any.
+    pub fn irq(&self) -> Option<u32> {
+        self.irq
+    }
+
+    fn regs(&self) -> &VirtioMmioRegs {
+        unsafe { self.regs.as_ref() }
+    }
+
+    fn init(&mut self) -> Result<(), ScmiError> {
+        let regs = self.regs();
+        regs.status.set(0);
+        let mut polls = 0;
+        while regs.status.get() != 0 {
+            if polls == RESET_POLLS {
+                error!("virtio-scmi device did not complete its reset");
+                return Err(ScmiError::TransportFailure);
+            }
+            polls += 1;
+            core::hint::spin_loop();
+        }
+        regs.status.write(DeviceStatus::ACKNOWLEDGE::SET);
+        regs.status.modify(DeviceStatus::DRIVER::SET);
+
+        regs.device_features_sel.set(0);
+        let mut features = regs.device_features.get() as u64;
+        regs.device_features_sel.set(1);
+        features |= (regs.device_features.get() as u64) << 32;
+        if features & VIRTIO_F_VERSION_1 == 0 {
+            error!("Legacy virtio devices are not supported");
+            return Err(ScmiError::NotSupported);
+        }
+        let features = features & (VIRTIO_F_VERSION_1 | VIRTIO_SCMI_F_P2A_CHANNELS);
+        regs.driver_features_sel.set(0);
+        regs.driver_features.set(features as u32);
+        regs.driver_features_sel.set(1);
+        regs.driver_features.set((features >> 32) as u32);
+        regs.status.modify(DeviceStatus::FEATURES_OK::SET);
+        if !regs.status.is_set(DeviceStatus::FEATURES_OK) {
+            error!("virtio-scmi device rejected features {features:#x}");
+            return Err(ScmiError::NotSupported);
+        }
+
+        self.cmdq.size = self
+            .setup_queue(CMDQ, CMDQ_OFFSET)?
+            .min(2 * CMD_SLOTS as u16);
+        for slot in 0..self.cmdq.size / 2 {
+            let tx = self.shmem.bus_address() + tx_buf(slot as usize);
+            let rx = self.shmem.bus_address() + rx_buf(slot as usize);
+            self.cmdq.set_desc(
+                2 * slot,
+                Descriptor {
+                    addr: tx as u64,
+                    len: 0,
+                    flags: VIRTQ_DESC_F_NEXT,
+                    next: 2 * slot + 1,
+                },
+            );
+            self.cmdq.set_desc(
+                2 * slot + 1,
+                Descriptor {
+                    addr: rx as u64,
+                    len: MSG_BUF_SIZE as u32,
+                    flags: VIRTQ_DESC_F_WRITE,
+                    next: 0,
+                },
+            );
+        }
+
+        if features & VIRTIO_SCMI_F_P2A_CHANNELS != 0 {
+            let size = self.setup_queue(EVENTQ, EVENTQ_OFFSET)?;
+            let mut eventq = Virtqueue {
+                size,
+                base: unsafe { self.shmem.address().as_ptr().add(EVENTQ_OFFSET) },
+                avail_idx: 0,
+                last_used: 0,
+            };
+            for i in 0..size.min(EVENT_BUFS as u16) {
+                let addr = self.shmem.bus_address() + event_buf(i as usize);
+                eventq.set_desc(
+                    i,
+                    Descriptor {
+                        addr: addr as u64,
+                        len: MSG_BUF_SIZE as u32,
+                        flags: VIRTQ_DESC_F_WRITE,
+                        next: 0,
+                    },
+                );
+                eventq.push(i);
+            }
+            self.eventq = Some(eventq);
+        }
+
+        // Every queue is configured before any is enabled.
+        self.enable_queue(CMDQ);
+        if self.eventq.is_some() {
+            self.enable_queue(EVENTQ);
+        }
+
+        self.regs().status.modify(DeviceStatus::DRIVER_OK::SET);
+        if self.eventq.is_some() {
+            self.notify(EVENTQ);
+        }
+        Ok(())
+    }
+
+    /// Describes the virtqueue at `offset` of the shared region to the
+    /// device and returns its size.
+    fn setup_queue(&mut self, index: u32, offset: usize) -> Result<u16, ScmiError> {
+        unsafe {
+            self.shmem
+                .address()
+                .as_ptr()
+                .add(offset)
+                .write_bytes(0, QUEUE_AREA);
+        }
+        wmb();
+
+        let bus = (self.shmem.bus_address() + offset) as u64;
+        let regs = self.regs();
+        regs.queue_sel.set(index);
+        if regs.queue_ready.get() != 0 {
+            error!("virtio-scmi queue {index} already in use");
+            return Err(ScmiError::TransportFailure);
+        }
+        let max = regs.queue_num_max.get();
+        if max < 2 {
+            error!("virtio-scmi queue {index} not available");
+            return Err(ScmiError::NotSupported);
+        }
+        let size = max.min(QUEUE_SIZE as u32);
+        regs.queue_num.set(size);
+
+        let desc = bus + DESC_OFFSET as u64;
+        let avail = bus + AVAIL_OFFSET as u64;
+        let used = bus + USED_OFFSET as u64;
+        regs.queue_desc_low.set(desc as u32);
+        regs.queue_desc_high.set((desc >> 32) as u32);
+        regs.queue_driver_low.set(avail as u32);
+        regs.queue_driver_high.set((avail >> 32) as u32);
+        regs.queue_device_low.set(used as u32);
+        regs.queue_device_high.set((used >> 32) as u32);
+        Ok(size as u16)
+    }
+
+    fn enable_queue(&self, index: u32) {
+        self.regs().queue_sel.set(index);
+        self.regs().queue_ready.set(1);
+    }
+
+    fn notify(&self, index: u32) {
+        self.regs().queue_notify.set(index);
+    }
+
+    /// Collects the commands answered by the device.
+    fn drain_cmdq(&mut self) {
+        while let Some(used) = self.cmdq.pop_used() {
+            let slot = used.id as usize / 2;
+            match self.slots.get(slot).copied() {
+                Some(Slot::Sent(token)) => {
+                    self.done.insert(token, (slot, used.len as usize));
+                }
+                Some(Slot::Abandoned) => self.slots[slot] = Slot::Free,
+                _ => warn!("virtio-scmi returned unused descriptor {}", used.id),
+            }
+        }
+    }
+}
+
+/// State of a pair of cmdq descriptors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Free,
+    /// Carrying the command with this token.
+    Sent(u16),
+    /// Still owned by the device, for a transfer nobody waits for anymore.
+    Abandoned,
+}
+
+fn tx_buf(slot: usize) -> usize {
+    BUF_OFFSET + 2 * slot * MSG_BUF_SIZE
+}
+
+fn rx_buf(slot: usize) -> usize {
+    tx_buf(slot) + MSG_BUF_SIZE
+}
+
+fn event_buf(id: usize) -> usize {
+    EVENT_BUF_OFFSET + id * MSG_BUF_SIZE
+}
+
+impl Transport for Virtio {
+    fn max_msg(&self) -> usize {
+        CMD_SLOTS
+    }
+
+    fn max_msg_size(&self) -> usize {
+        MAX_MSG_SIZE
+    }
+
+    fn sync_cmds_completed_on_ret(&self) -> bool {
+        false
+    }
+
+    fn chan_available(&self, idx: usize) -> bool {
+        match idx {
+            0 => true,
+            1 => self.eventq.is_some(),
+            _ => false,
+        }
+    }
+
+    fn no_completion_irq(&self) -> bool {
+        self.irq.is_none()
+    }
+
+    fn send_message(&mut self, xfer: &Xfer) -> Result<(), ScmiError> {
+        if xfer.tx.len() > MAX_MSG_SIZE {
+            warn!(
+                "Payload of {} bytes too long for {:?}",
+                xfer.tx.len(),
+                xfer.hdr
+            );
+            return Err(ScmiError::InvalidLength(xfer.tx.len()));
+        }
+
+        let slots = (self.cmdq.size / 2) as usize;
+        let Some(slot) = self.slots[..slots].iter().position(|s| *s == Slot::Free) else {
+            return Err(ScmiError::Busy);
+        };
+
+        let len = self
+            .shmem
+            .msg_slice(tx_buf(slot), MSG_BUF_SIZE)
+            .tx_prepare(xfer)?;
+        let head = 2 * slot as u16;
+        self.cmdq.set_desc_len(head, len as u32);
+        self.slots[slot] = Slot::Sent(xfer.hdr.seq);
+        trace!("Sending virtio message {:?} in slot {slot}", xfer.hdr);
+        self.cmdq.push(head);
+        self.notify(CMDQ);
+        Ok(())
+    }
+
+    fn poll_done(&mut self, xfer: &Xfer) -> bool {
+        self.drain_cmdq();
+        self.done.contains_key(&xfer.hdr.seq)
+    }
+
+    fn fetch_response(&mut self, xfer: &mut Xfer) -> Result<(), ScmiError> {
+        let Some((slot, len)) = self.done.remove(&xfer.hdr.seq) else {
+            return Err(ScmiError::ProtocolError);
+        };
+        let res = self
+            .shmem
+            .msg_slice(rx_buf(slot), MSG_BUF_SIZE)
+            .fetch_msg_response(xfer, len, MAX_MSG_SIZE);
+        self.slots[slot] = Slot::Free;
+        res
+    }
+
+    /// Frees the slot of a transfer given up on before its answer was read.
+    /// The device may still own the buffers, in which case the slot is only
+    /// freed once it hands them back.
+    fn mark_txdone(&mut self, xfer: &Xfer) {
+        let token = xfer.hdr.seq;
+        if let Some((slot, _)) = self.done.remove(&token) {
+            self.slots[slot] = Slot::Free;
+        } else if let Some(slot) = self.slots.iter_mut().find(|s| **s == Slot::Sent(token)) {
+            *slot = Slot::Abandoned;
+        }
+    }
+
+    /// Acknowledges the interrupt and collects every answered command, any
+    /// number of which may have completed.
+    fn ack_irq(&mut self) {
+        let status = self.regs().interrupt_status.get();
+        self.regs().interrupt_ack.set(status);
+        self.drain_cmdq();
+    }
+
+    fn fetch_notification(&mut self) -> Result<Option<Notification>, ScmiError> {
+        let Some(used) = self.eventq.as_mut().and_then(Virtqueue::pop_used) else {
+            return Ok(None);
+        };
+        let id = used.id as usize;
+        if id >= EVENT_BUFS {
+            warn!("virtio-scmi returned unknown event buffer {id}");
+            return Err(ScmiError::ProtocolError);
+        }
+        let res = self
+            .shmem
+            .msg_slice(event_buf(id), MSG_BUF_SIZE)
+            .fetch_msg_notification(used.len as usize, MAX_MSG_SIZE);
+        if let Some(eventq) = self.eventq.as_mut() {
+            eventq.push(id as u16);
+        }
+        self.notify(EVENTQ);
+        res.map(Some)
+    }
+}
+
+impl Drop for Virtio {
+    fn drop(&mut self) {
+        // Stop the device from touching the shared region.
+        self.regs().status.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, vec, vec::Vec};
+
+    use super::*;
+    use crate::{
+        FuturePoll, MsgHeader, MsgType, Platform, Scmi, ShmemLayout,
+        emulator::{EmulatedClock, Emulator},
+    };
+
+    const CLOCK: u8 = 0x14;
+    const CLOCK_RATE_GET: u8 = 0x6;
+
+    /// virtio-scmi device model answering from an [`Emulator`].
+    ///
+    /// The register block is plain memory, and the shared region is identity
+    /// mapped, so descriptors point right at the buffers. The device only
+    /// acts when the test calls [`FakeDevice::process`] or
+    /// [`FakeDevice::notify`].
+    struct FakeDevice {
+        regs: NonNull<u32>,
+        shmem: NonNull<u8>,
+        emulator: Emulator,
+        cmdq_avail: u16,
+        cmdq_used: u16,
+        eventq_avail: u16,
+        eventq_used: u16,
+        // Backs `regs` and `shmem`.
+        _mem: (Box<[u32]>, Box<[u32]>),
+    }
+
+    impl FakeDevice {
+        fn new() -> Self {
+            let mut regs = vec![0u32; size_of::<VirtioMmioRegs>() / 4].into_boxed_slice();
+            let mut set = |offset: usize, val: u32| regs[offset / size_of::<u32>()] = val;
+            set(0x000, VIRTIO_MMIO_MAGIC);
+            set(0x004, VIRTIO_MMIO_VERSION);
+            set(0x008, Virtio::DEVICE_ID);
+            // Read for both feature words: VIRTIO_F_VERSION_1 in the high one,
+            // VIRTIO_SCMI_F_P2A_CHANNELS in the low one.
+            set(0x010, 1);
+            set(0x034, QUEUE_SIZE as u32);
+            let mut shmem = vec![0u32; Virtio::SHMEM_SIZE / 4].into_boxed_slice();
+            FakeDevice {
+                regs: NonNull::new(regs.as_mut_ptr()).unwrap(),
+                shmem: NonNull::new(shmem.as_mut_ptr()).unwrap().cast(),
+                emulator: Emulator {
+                    clocks: vec![EmulatedClock::new("cpu", 1_200_000_000)],
+                    ..Default::default()
+                },
+                cmdq_avail: 0,
+                cmdq_used: 0,
+                eventq_avail: 0,
+                eventq_used: 0,
+                _mem: (regs, shmem),
+            }
+        }
+
+        /// The shared region, identity mapped.
+        fn region(&self) -> Shmem {
+            let bus = self.shmem.as_ptr() as usize;
+            unsafe { Shmem::new(self.shmem, bus, Virtio::SHMEM_SIZE, ShmemLayout::Msg) }.unwrap()
+        }
+
+        fn driver(&self) -> Virtio {
+            unsafe { Virtio::new(self.regs.cast(), self.region(), Some(42)) }.unwrap()
+        }
+
+        fn reg(&self, offset: usize) -> u32 {
+            unsafe { self.regs.add(offset / 4).read_volatile() }
+        }
+
+        fn queue(&self, offset: usize) -> *mut u8 {
+            unsafe { self.shmem.as_ptr().add(offset) }
+        }
+
+        fn desc(&self, queue: usize, i: u16) -> Descriptor {
+            unsafe {
+                self.queue(queue)
+                    .add(DESC_OFFSET)
+                    .cast::<Descriptor>()
+                    .add(i as usize)
+                    .read_volatile()
+            }
+        }
+
+        /// Takes the next chain made available on a queue.
+        fn pop_avail(&self, queue: usize, seen: &mut u16) -> Option<u16> {
+            unsafe {
+                let avail = self.queue(queue).add(AVAIL_OFFSET).cast::<u16>();
+                if avail.add(1).read_volatile() == *seen {
+                    return None;
+                }
+                let head = avail.add(2 + (*seen as usize % QUEUE_SIZE)).read_volatile();
+                *seen = seen.wrapping_add(1);
+                Some(head)
+            }
+        }
+
+        fn push_used(&self, queue: usize, idx: &mut u16, id: u16, len: usize) {
+            unsafe {
+                let used = self.queue(queue).add(USED_OFFSET);
+                let elem = UsedElem {
+                    id: id as u32,
+                    len: len as u32,
+                };
+                used.add(2 * size_of::<u16>())
+                    .cast::<UsedElem>()
+                    .add(*idx as usize % QUEUE_SIZE)
+                    .write_volatile(elem);
+                *idx = idx.wrapping_add(1);
+                used.add(size_of::<u16>())
+                    .cast::<u16>()
+                    .write_volatile(*idx);
+                self.regs.add(0x060 / 4).write_volatile(1);
+            }
+        }
+
+        /// Answers every command made available, returns how many.
+        fn process(&mut self) -> usize {
+            let mut answered = 0;
+            let mut seen = self.cmdq_avail;
+            while let Some(head) = self.pop_avail(CMDQ_OFFSET, &mut seen) {
+                let tx = self.desc(CMDQ_OFFSET, head);
+                let rx = self.desc(CMDQ_OFFSET, tx.next);
+                assert_eq!(tx.flags, VIRTQ_DESC_F_NEXT);
+                assert_eq!(rx.flags, VIRTQ_DESC_F_WRITE);
+
+                let msg = unsafe {
+                    core::slice::from_raw_parts(tx.addr as usize as *const u8, tx.len as usize)
+                };
+                let raw = u32::from_le_bytes(msg[..4].try_into().unwrap());
+                let hdr = MsgHeader::unpack(raw);
+                let (status, resp) = match self.emulator.handle(hdr.protocol_id, hdr.id, &msg[4..])
+                {
+                    Ok(resp) => (ScmiError::SUCCESS, resp),
+                    Err(status) => (status, Vec::new()),
+                };
+                let mut answer = Vec::new();
+                answer.extend_from_slice(&raw.to_le_bytes());
+                answer.extend_from_slice(&status.to_le_bytes());
+                answer.extend_from_slice(&resp);
+                assert!(answer.len() <= rx.len as usize);
+                unsafe {
+                    let dest = rx.addr as usize as *mut u8;
+                    dest.copy_from_nonoverlapping(answer.as_ptr(), answer.len());
+                }
+                let mut used = self.cmdq_used;
+                self.push_used(CMDQ_OFFSET, &mut used, head, answer.len());
+                self.cmdq_used = used;
+                answered += 1;
+            }
+            self.cmdq_avail = seen;
+            answered
+        }
+
+        /// Sends a notification in the next event buffer.
+        fn notify(&mut self, protocol_id: u8, msg_id: u8, payload: &[u8]) {
+            let mut seen = self.eventq_avail;
+            let id = self
+                .pop_avail(EVENTQ_OFFSET, &mut seen)
+                .expect("no event buffer");
+            self.eventq_avail = seen;
+            let desc = self.desc(EVENTQ_OFFSET, id);
+            let hdr = MsgHeader {
+                id: msg_id,
+                protocol_id,
+                type_: MsgType::Notification,
+                ..Default::default()
+            };
+            let mut msg = Vec::new();
+            msg.extend_from_slice(&hdr.pack().to_le_bytes());
+            msg.extend_from_slice(payload);
+            unsafe {
+                let dest = desc.addr as usize as *mut u8;
+                dest.copy_from_nonoverlapping(msg.as_ptr(), msg.len());
+            }
+            let mut used = self.eventq_used;
+            self.push_used(EVENTQ_OFFSET, &mut used, id, msg.len());
+            self.eventq_used = used;
+        }
+    }
+
+    fn rate_get(token: u16, clock_id: u32) -> Xfer {
+        let mut xfer = Xfer::new(CLOCK_RATE_GET, MAX_MSG_SIZE).unwrap();
+        xfer.hdr.protocol_id = CLOCK;
+        xfer.hdr.seq = token;
+        xfer.tx.extend_from_slice(&clock_id.to_le_bytes());
+        xfer
+    }
+
+    #[test]
+    fn bring_up() {
+        let dev = FakeDevice::new();
+        let virtio = dev.driver();
+        assert!(virtio.chan_available(0));
+        assert!(virtio.chan_available(1));
+        assert!(!virtio.no_completion_irq());
+        let status = dev.reg(0x070);
+        assert_eq!(status & 0b1111, 0b1111, "status {status:#x}");
+        assert_eq!(dev.reg(0x044), 1, "last queue enabled");
+        assert_eq!(dev.reg(0x050), EVENTQ, "event buffers handed over");
+    }
+
+    #[test]
+    fn command_and_response() {
+        let mut dev = FakeDevice::new();
+        let mut virtio = dev.driver();
+        let mut xfer = rate_get(3, 0);
+        virtio.send_message(&xfer).unwrap();
+        assert_eq!(dev.reg(0x050), CMDQ, "cmdq notified");
+        assert!(!virtio.poll_done(&xfer));
+
+        assert_eq!(dev.process(), 1);
+        virtio.ack_irq();
+        assert_eq!(virtio.completed_token(), None);
+        assert!(virtio.poll_done(&xfer));
+        virtio.fetch_response(&mut xfer).unwrap();
+        assert_eq!(xfer.rx, 1_200_000_000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn error_status() {
+        let mut dev = FakeDevice::new();
+        let mut virtio = dev.driver();
+        let mut xfer = rate_get(1, 7);
+        virtio.send_message(&xfer).unwrap();
+        dev.process();
+        assert!(virtio.poll_done(&xfer));
+        assert_eq!(virtio.fetch_response(&mut xfer), Err(ScmiError::NotFound));
+    }
+
+    #[test]
+    fn notification() {
+        let mut dev = FakeDevice::new();
+        let mut virtio = dev.driver();
+        assert!(matches!(virtio.fetch_notification(), Ok(None)));
+
+        for i in 0..2 * EVENT_BUFS as u32 {
+            dev.notify(CLOCK, 0x1, &i.to_le_bytes());
+            let notif = virtio.fetch_notification().unwrap().unwrap();
+            assert_eq!(notif.hdr.protocol_id, CLOCK);
+            assert_eq!(notif.hdr.id, 0x1);
+            assert_eq!(notif.hdr.type_, MsgType::Notification);
+            assert_eq!(notif.payload, i.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn slots_are_recycled() {
+        let mut dev = FakeDevice::new();
+        let mut virtio = dev.driver();
+        let mut xfers: Vec<Xfer> = (0..CMD_SLOTS as u16).map(|t| rate_get(t, 0)).collect();
+        for xfer in &xfers {
+            virtio.send_message(xfer).unwrap();
+        }
+        let extra = rate_get(CMD_SLOTS as u16, 0);
+        assert_eq!(virtio.send_message(&extra), Err(ScmiError::Busy));
+
+        assert_eq!(dev.process(), CMD_SLOTS);
+        // Answers are matched by token, whatever the order they are read in.
+        for xfer in xfers.iter_mut().rev() {
+            assert!(virtio.poll_done(xfer));
+            virtio.fetch_response(xfer).unwrap();
+        }
+        for round in 0..3 {
+            let mut xfer = rate_get(round, 0);
+            virtio.send_message(&xfer).unwrap();
+            assert_eq!(dev.process(), 1);
+            assert!(virtio.poll_done(&xfer));
+            virtio.fetch_response(&mut xfer).unwrap();
+        }
+    }
+
+    #[test]
+    fn abandoned_answer_frees_its_slot() {
+        let mut dev = FakeDevice::new();
+        let mut virtio = dev.driver();
+        let xfer = rate_get(9, 1);
+        virtio.send_message(&xfer).unwrap();
+        dev.process();
+        assert!(virtio.poll_done(&xfer));
+        // Given up on without reading the answer.
+        virtio.mark_txdone(&xfer);
+        assert!(virtio.done.is_empty());
+        assert!(virtio.slots.iter().all(|s| *s == Slot::Free));
+
+        // The token is reused and gets its own answer, not the stale one.
+        let mut xfer = rate_get(9, 0);
+        virtio.send_message(&xfer).unwrap();
+        dev.process();
+        assert!(virtio.poll_done(&xfer));
+        virtio.fetch_response(&mut xfer).unwrap();
+        assert_eq!(xfer.rx, 1_200_000_000u64.to_le_bytes());
+        virtio.mark_txdone(&xfer);
+        assert!(virtio.slots.iter().all(|s| *s == Slot::Free));
+    }
+
+    #[test]
+    fn slot_abandoned_in_flight() {
+        let mut dev = FakeDevice::new();
+        let mut virtio = dev.driver();
+        let xfer = rate_get(4, 0);
+        virtio.send_message(&xfer).unwrap();
+        virtio.mark_txdone(&xfer);
+        assert!(virtio.slots.contains(&Slot::Abandoned), "device owns it");
+
+        // The late answer frees the slot without being taken for anyone's.
+        let next = rate_get(4, 0);
+        virtio.send_message(&next).unwrap();
+        assert_eq!(dev.process(), 2);
+        virtio.ack_irq();
+        assert_eq!(virtio.done.len(), 1);
+        assert_eq!(
+            virtio.slots.iter().filter(|s| **s == Slot::Free).count(),
+            CMD_SLOTS - 1
+        );
+    }
+
+    #[test]
+    fn boxed_from_compatible() {
+        let mut dev = FakeDevice::new();
+        let mmio = dev.regs.cast();
+        let res =
+            unsafe { Virtio::from_compatible(Virtio::MMIO_COMPATIBLE, mmio, dev.region(), None) };
+        assert!(matches!(res, Err(ScmiError::NotSupported)));
+
+        let virtio =
+            unsafe { Virtio::from_compatible(Virtio::COMPATIBLE, mmio, dev.region(), Some(42)) };
+        let transport: Box<dyn Transport> = Box::new(virtio.unwrap());
+        let scmi = Scmi::new(transport).unwrap();
+        let mut clock = scmi.open_protocol(CLOCK);
+        let mut xfer = clock
+            .raw_xfer(CLOCK_RATE_GET, &0u32.to_le_bytes(), 8)
+            .unwrap();
+        assert!(matches!(xfer.poll_completion(), Err(nb::Error::WouldBlock)));
+
+        assert_eq!(dev.process(), 1);
+        scmi.handle_irq();
+        let rate = nb::block!(xfer.poll_completion()).unwrap();
+        assert_eq!(rate, 1_200_000_000u64.to_le_bytes());
+    }
+}
@@ -17,7 +17,7 @@ mod tests {
     use log::info;
     use nb::block;
     use num_align::NumAlign;
-    use arm_scmi::{Scmi, Shmem, Smc};
+    use arm_scmi::{Scmi, Shmem, ShmemLayout, Smc};
 
     #[test]
     fn it_works() {
@@ -60,15 +60,19 @@ mod tests {
 
         let irq_num = node.find_property("a2p").map(|irq_prop| irq_prop.u32());
 
-        let shmem = Shmem {
-            address: shmem_addr,
-            bus_address: shmem_reg.child_bus_address as usize,
-            size: shmem_reg.size.unwrap(),
+        let shmem = unsafe {
+            Shmem::new(
+                shmem_addr,
+                shmem_reg.child_bus_address as usize,
+                shmem_reg.size.unwrap(),
+                ShmemLayout::Smt,
+            )
+            .unwrap()
         };
-        let kind = Smc::new(func_id, irq_num);
-        let scmi = Scmi::new(kind, shmem);
+        let kind = Smc::new(shmem, func_id, irq_num);
+        let scmi = Scmi::new(kind).unwrap();
 
-        let mut pclk = scmi.protocol_clk();
+        let mut pclk = scmi.protocol_clk().unwrap();
 
         let ls = [
             (0u32, "clk0", 0x30a32c00),